@@ -0,0 +1,271 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use subprocess::Exec;
+
+use crate::error::{Result, UpdateError};
+use crate::history::{self, History};
+use crate::{git, term, UpdateArgs, UpdateQtile};
+
+/// Several commits given to `--commit`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Commits {
+    /// `A..B`: the commits reachable from `B` but not from `A`.
+    Range { from: String, to: String },
+    /// `A,B,C`
+    List(Vec<String>),
+}
+
+impl Commits {
+    /// `None` when `spec` is a single commit.
+    pub fn parse(spec: &str) -> Result<Option<Self>> {
+        let invalid = |reason: &str| UpdateError::InvalidCommits {
+            spec: spec.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if spec.contains("...") {
+            return Err(invalid("`A...B` ranges aren't supported, use `A..B`"));
+        }
+        if let Some((from, to)) = spec.split_once("..") {
+            if from.trim().is_empty() || to.trim().is_empty() {
+                return Err(invalid("both ends of the range are needed"));
+            }
+            Ok(Some(Self::Range {
+                from: from.trim().to_owned(),
+                to: to.trim().to_owned(),
+            }))
+        } else if spec.contains(',') {
+            let commits = spec
+                .split(',')
+                .map(str::trim)
+                .filter(|commit| !commit.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            if commits.is_empty() {
+                return Err(invalid("the list has no commits"));
+            }
+            Ok(Some(Self::List(commits)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The commits to build with their summaries, oldest first. Of a range
+    /// only every `step`th commit is kept, and always the last one.
+    fn resolve(self, url: &str, step: usize) -> Result<Vec<(String, String)>> {
+        match self {
+            Self::List(commits) => Ok(commits
+                .into_iter()
+                .map(|commit| (commit, String::new()))
+                .collect()),
+            Self::Range { from, to } => {
+                let mut commits = git::commit_range(url, &from, &to)?;
+                commits.reverse();
+                Ok(every_nth(commits, step)
+                    .into_iter()
+                    .map(|(oid, summary)| (oid.to_string(), summary))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Every `step`th of `items` from the first, and always the last one.
+fn every_nth<T>(items: Vec<T>, step: usize) -> Vec<T> {
+    let last = items.len().saturating_sub(1);
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| index % step == 0 || *index == last)
+        .map(|(_, item)| item)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Good,
+    Bad,
+    Skipped,
+    BuildFailed,
+}
+
+impl Verdict {
+    fn name(self) -> &'static str {
+        match self {
+            Verdict::Good => "good",
+            Verdict::Bad => "bad",
+            Verdict::Skipped => "skipped",
+            Verdict::BuildFailed => "build failed",
+        }
+    }
+}
+
+/// How one commit of a batch went.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub commit: String,
+    pub summary: String,
+    /// History id of the run that built it.
+    pub build: u64,
+    pub verdict: Verdict,
+}
+
+pub fn results_path(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("batch.json")
+}
+
+fn save(results: &[BatchResult], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(results)?)?;
+    Ok(())
+}
+
+/// Run the test command, it passes if it exits successfully.
+fn test(command: &str, commit: &str) -> Result<Verdict> {
+    log::info!("running `{command}`");
+    let exit_status = Exec::shell(command)
+        .env("UPDATE_QTILE_COMMIT", commit)
+        .join()?;
+    Ok(if exit_status.success() {
+        Verdict::Good
+    } else {
+        Verdict::Bad
+    })
+}
+
+/// Ask how the installed commit behaves, `None` to stop the batch.
+fn ask(commit: &str) -> Result<Option<Verdict>> {
+    loop {
+        let ans = term::ask(
+            &format!("is `{commit}` [g]ood or [b]ad? ([s]kip, [q]uit)"),
+            "testing commits without --test-cmd",
+        )?;
+        match ans.as_str() {
+            "g" | "good" => return Ok(Some(Verdict::Good)),
+            "b" | "bad" => return Ok(Some(Verdict::Bad)),
+            "s" | "skip" => return Ok(Some(Verdict::Skipped)),
+            "q" | "quit" => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Build and install every commit in turn, testing each one before moving to
+/// the next.
+pub fn batch(args: UpdateArgs, commits: Commits, cache_home: &Path) -> Result<()> {
+    if args.test_cmd.is_none() && !term::interactive() {
+        return Err(UpdateError::NoTerminal(
+            "testing commits without --test-cmd".to_owned(),
+        ));
+    }
+    let url = git::remote_url(args.fork.as_deref(), args.path.as_deref());
+    let commits = commits.resolve(&url, args.step.map_or(1, |step| step as usize))?;
+    let path = results_path(cache_home);
+    let mut results = vec![];
+    for (index, (commit, summary)) in commits.iter().enumerate() {
+        log::info!(
+            "[{}/{}] building `{commit}` {summary}",
+            index + 1,
+            commits.len()
+        );
+        let build = History::load(&history::history_path(cache_home))?.next_id();
+        let run = UpdateQtile::new(UpdateArgs {
+            commit: Some(commit.clone()),
+            ..args.clone()
+        })
+        .run();
+        let verdict = match run {
+            Err(err @ UpdateError::Interrupted { .. }) => {
+                save(&results, &path)?;
+                return Err(err);
+            }
+            Err(err) => {
+                log::error!("{err}");
+                Verdict::BuildFailed
+            }
+            Ok(()) => match &args.test_cmd {
+                Some(command) => test(command, commit)?,
+                None => match ask(commit)? {
+                    Some(verdict) => verdict,
+                    None => break,
+                },
+            },
+        };
+        results.push(BatchResult {
+            commit: commit.clone(),
+            summary: summary.clone(),
+            build,
+            verdict,
+        });
+        save(&results, &path)?;
+    }
+    for result in &results {
+        println!(
+            "{}",
+            term::fit(format!(
+                "{:<12} {:<13} build {:<4} {}",
+                result.commit.chars().take(12).collect::<String>(),
+                result.verdict.name(),
+                result.build,
+                result.summary
+            ))
+        );
+    }
+    log::info!("results saved to {:?}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_commit() {
+        assert_eq!(Commits::parse("abc1234").unwrap(), None);
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(
+            Commits::parse("v0.28.0..master").unwrap(),
+            Some(Commits::Range {
+                from: "v0.28.0".to_owned(),
+                to: "master".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for spec in ["a...b", "..b", "a..", ".."] {
+            assert!(Commits::parse(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn list() {
+        assert_eq!(
+            Commits::parse("abc, def,,ghé").unwrap(),
+            Some(Commits::List(vec![
+                "abc".to_owned(),
+                "def".to_owned(),
+                "ghé".to_owned()
+            ]))
+        );
+        assert!(Commits::parse(",,").is_err());
+    }
+
+    #[test]
+    fn step() {
+        let commits = (1..=7).collect::<Vec<_>>();
+        assert_eq!(every_nth(commits.clone(), 1), commits);
+        assert_eq!(every_nth(commits.clone(), 3), [1, 4, 7]);
+        // the last commit is always built
+        assert_eq!(every_nth(commits.clone(), 4), [1, 5, 7]);
+        assert_eq!(every_nth(commits, 10), [1, 7]);
+        assert!(every_nth(Vec::<u8>::new(), 2).is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use subprocess::{Exec, PopenError, Redirection};
+
+use crate::error::{Result, UpdateError};
+use crate::process;
+
+/// Startups measured per benchmark, the median is reported.
+const SAMPLES: usize = 3;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The config shipped with qtile, so that the benchmark doesn't run the
+/// autostart hooks of the user's config.
+pub fn default_config() -> Result<PathBuf> {
+    let capture = Exec::cmd("python")
+        .args(&["-c", "import libqtile.resources as r; print(r.__path__[0])"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    if !capture.success() {
+        return Err(UpdateError::Benchmark(
+            "libqtile can't be imported".to_owned(),
+        ));
+    }
+    Ok(PathBuf::from(capture.stdout_str().trim()).join("default_config.py"))
+}
+
+fn responds(socket: &Path) -> bool {
+    Exec::cmd("qtile")
+        .args(&["cmd-obj", "-f", "status", "-s"])
+        .arg(socket)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .is_ok_and(|capture| capture.success())
+}
+
+/// Time from starting qtile on a headless X server until it answers IPC.
+fn startup_once(config: &Path) -> Result<Duration> {
+    let scratch = tempfile::tempdir()?;
+    let socket = scratch.path().join("qtile.socket");
+    let start = Instant::now();
+    let mut server = match Exec::cmd("xvfb-run")
+        .args(&["-a", "qtile", "start", "-b", "x11", "-c"])
+        .arg(config)
+        .arg("-s")
+        .arg(&socket)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .popen()
+    {
+        Ok(server) => server,
+        Err(PopenError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(UpdateError::MissingTool("xvfb-run".to_owned()));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let result = loop {
+        if responds(&socket) {
+            break Ok(start.elapsed());
+        }
+        if process::interrupted() {
+            break Err(UpdateError::Interrupted {
+                command: "qtile start".to_owned(),
+            });
+        }
+        if server.poll().is_some() {
+            break Err(UpdateError::Benchmark(
+                "qtile exited during startup".to_owned(),
+            ));
+        }
+        if start.elapsed() >= STARTUP_TIMEOUT {
+            break Err(UpdateError::Benchmark(format!(
+                "qtile didn't answer within {}s",
+                STARTUP_TIMEOUT.as_secs()
+            )));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let _ = Exec::cmd("qtile")
+        .args(&["cmd-obj", "-f", "shutdown", "-s"])
+        .arg(&socket)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .join();
+    if let Ok(None) = server.wait_timeout(Duration::from_secs(5)) {
+        let _ = server.terminate();
+        let _ = server.wait();
+    }
+    result
+}
+
+/// Median startup time of the installed qtile with `config`.
+pub fn startup_time(config: &Path) -> Result<Duration> {
+    let mut samples = (0..SAMPLES)
+        .map(|_| startup_once(config))
+        .collect::<Result<Vec<_>>>()?;
+    samples.sort();
+    Ok(samples[SAMPLES / 2])
+}
+
+pub fn log_delta(before: Duration, after: Duration) {
+    let change = (after.as_secs_f64() / before.as_secs_f64() - 1.0) * 100.0;
+    let message = format!(
+        "startup time: {:.2}s -> {:.2}s ({change:+.0}%)",
+        before.as_secs_f64(),
+        after.as_secs_f64()
+    );
+    // below 10% it's mostly noise
+    if change > 10.0 {
+        log::warn!("{message}");
+    } else {
+        log::info!("{message}");
+    }
+}
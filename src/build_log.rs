@@ -0,0 +1,193 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{Result, UpdateError};
+use crate::process;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Start of the lines that separate the steps of a run in the log.
+const SECTION_MARKER: &str = "-------------------------------";
+/// Start of the lines makepkg, pacman and python report failures with.
+const ERROR_MARKERS: [&str; 4] = [
+    "==> ERROR:",
+    "error:",
+    "Traceback (most recent call last):",
+    "FAILED",
+];
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LogArgs {
+    /// Keep printing what is written to the log until interrupted
+    #[arg(short, long, default_value_t = false)]
+    follow: bool,
+    /// Only show the last failing section of the log, or only errors when
+    /// following it
+    #[arg(short, long, default_value_t = false)]
+    errors_only: bool,
+    /// Number of lines to show, with --errors-only the number of lines before
+    /// the first error
+    #[arg(short = 'n', long, default_value_t = 40)]
+    lines: usize,
+}
+
+/// The log of the running or last run, which is in the build tree until the
+/// build is moved into the cache.
+fn current(dirs: &[&Path]) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join("install.log"))
+        .filter_map(|path| Some((path.metadata().ok()?.modified().ok()?, path)))
+        .max()
+        .map(|(_, path)| path)
+}
+
+fn is_error(line: &str) -> bool {
+    let line = line.trim_start();
+    ERROR_MARKERS.iter().any(|marker| line.starts_with(marker))
+}
+
+/// The last section with an error, from `context` lines before its first
+/// error to its end.
+fn last_failure<'a>(lines: &'a [&'a str], context: usize) -> Option<&'a [&'a str]> {
+    let mut end = lines.len();
+    loop {
+        let start = lines[..end]
+            .iter()
+            .rposition(|line| line.starts_with(SECTION_MARKER))
+            .unwrap_or(0);
+        if let Some(first_error) = lines[start..end].iter().position(|line| is_error(line)) {
+            let from = (start + first_error).saturating_sub(context).max(start);
+            return Some(&lines[from..end]);
+        }
+        if start == 0 {
+            return None;
+        }
+        end = start;
+    }
+}
+
+/// Print what is appended to `f` until interrupted, `pending` is the last
+/// line read so far if it is incomplete.
+fn follow(mut f: std::fs::File, mut pending: String, errors_only: bool) -> Result<()> {
+    let mut buffer = vec![];
+    while !process::interrupted() {
+        // a new run recreates the log
+        let position = f.stream_position()?;
+        if f.metadata()?.len() < position {
+            f.seek(SeekFrom::Start(0))?;
+            pending.clear();
+        }
+        buffer.clear();
+        if f.read_to_end(&mut buffer)? == 0 {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        pending.push_str(&String::from_utf8_lossy(&buffer));
+        while let Some(end) = pending.find('\n') {
+            let line: String = pending.drain(..=end).collect();
+            let line = line.trim_end_matches('\n');
+            if !errors_only || is_error(line) {
+                println!("{line}");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn log(args: &LogArgs, dirs: &[&Path]) -> Result<()> {
+    let Some(path) = current(dirs) else {
+        return Err(UpdateError::NoLog);
+    };
+    log::info!("showing {:?}", path);
+    let mut f = std::fs::File::open(&path)?;
+    let mut contents = vec![];
+    f.read_to_end(&mut contents)?;
+    let contents = String::from_utf8_lossy(&contents);
+    // keep an incomplete last line for when the rest of it is written
+    let (complete, pending) = match contents.rfind('\n') {
+        Some(end) => contents.split_at(end + 1),
+        None => ("", contents.as_ref()),
+    };
+    let lines: Vec<&str> = complete.lines().collect();
+    if args.errors_only && !args.follow {
+        match last_failure(&lines, args.lines) {
+            Some(failure) => failure.iter().for_each(|line| println!("{line}")),
+            None => log::info!("no errors found in {:?}", path),
+        }
+        return Ok(());
+    }
+    let shown: Vec<&str> = lines
+        .into_iter()
+        .filter(|line| !args.errors_only || is_error(line))
+        .collect();
+    for line in &shown[shown.len().saturating_sub(args.lines)..] {
+        println!("{line}");
+    }
+    if args.follow {
+        follow(f, pending.to_owned(), args.errors_only)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTION: &str = "------------------------------- build -------------------------------";
+
+    #[test]
+    fn errors() {
+        assert!(is_error("==> ERROR: A failure occurred in build()."));
+        assert!(is_error(
+            "error: failed to commit transaction (conflicting files)"
+        ));
+        assert!(is_error("  Traceback (most recent call last):"));
+        assert!(is_error("FAILED test/test_bar.py::test_basic"));
+        assert!(!is_error("==> Finished making: qtile-git"));
+        assert!(!is_error("checking for errors"));
+    }
+
+    #[test]
+    fn no_failure() {
+        assert_eq!(last_failure(&[], 3), None);
+        assert_eq!(last_failure(&[SECTION, "==> Finished making"], 3), None);
+    }
+
+    #[test]
+    fn failure_with_context() {
+        let lines = [SECTION, "a", "b", "c", "==> ERROR: build failed", "d"];
+        assert_eq!(
+            last_failure(&lines, 2),
+            Some(&["b", "c", "==> ERROR: build failed", "d"][..])
+        );
+    }
+
+    #[test]
+    fn context_stops_at_the_section() {
+        let lines = ["x", SECTION, "a", "error: oops", "b"];
+        assert_eq!(
+            last_failure(&lines, 10),
+            Some(&[SECTION, "a", "error: oops", "b"][..])
+        );
+    }
+
+    #[test]
+    fn last_failing_section() {
+        let lines = [
+            SECTION,
+            "error: first",
+            SECTION,
+            "error: second",
+            "a",
+            SECTION,
+            "all good",
+        ];
+        assert_eq!(last_failure(&lines, 0), Some(&["error: second", "a"][..]));
+    }
+
+    #[test]
+    fn failure_before_any_section() {
+        let lines = ["a", "error: oops", SECTION, "all good"];
+        assert_eq!(last_failure(&lines, 1), Some(&["a", "error: oops"][..]));
+    }
+}
@@ -0,0 +1,253 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::format_size;
+use crate::error::{Result, UpdateError};
+use crate::xdg;
+
+/// Room a qtile-git build takes. The clone of qtile, its checkout under `src/`
+/// and the built wheel come to under 100 MiB and the package to a few MiB,
+/// this leaves some margin over that.
+const REQUIRED: u64 = 256 * 1024 * 1024;
+
+/// What to do when the build directory is too small.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LowSpace {
+    Warn,
+    /// Build elsewhere for this run.
+    #[default]
+    Relocate,
+    Fail,
+}
+
+/// The value of the last `BUILDDIR=` assignment in the makepkg.conf
+/// `contents`, with `home` for `~` and `$HOME`.
+fn conf_builddir(contents: &str, home: &str) -> Option<String> {
+    let value = contents
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("BUILDDIR="))
+        .next_back()?;
+    let value = value.split(" #").next().unwrap_or_default().trim();
+    let value = value.trim_matches(|c| c == '"' || c == '\'');
+    Some(
+        value
+            .replacen('~', home, usize::from(value.starts_with('~')))
+            .replace("${HOME}", home)
+            .replace("$HOME", home),
+    )
+}
+
+/// The makepkg.conf files in the order makepkg reads them.
+fn conf_files(makepkg_conf: Option<&Path>) -> Vec<PathBuf> {
+    let system = makepkg_conf.unwrap_or(Path::new("/etc/makepkg.conf"));
+    let mut files = vec![system.to_path_buf()];
+    let mut dropins = glob::glob(&format!("{}.d/*.conf", system.display()))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect::<Vec<_>>();
+    dropins.sort();
+    files.extend(dropins);
+    files.push(xdg::config_home().join("pacman").join("makepkg.conf"));
+    files
+}
+
+/// Where makepkg builds: `BUILDDIR` from the environment or makepkg.conf, or
+/// next to the PKGBUILD in `startdir`.
+pub fn build_dir(makepkg_conf: Option<&Path>, startdir: &Path) -> PathBuf {
+    std::env::var("BUILDDIR")
+        .ok()
+        .or_else(|| {
+            // the last file read wins
+            conf_files(makepkg_conf).iter().rev().find_map(|path| {
+                let contents = std::fs::read_to_string(path).ok()?;
+                conf_builddir(&contents, &xdg::home())
+            })
+        })
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| startdir.to_path_buf(), PathBuf::from)
+}
+
+/// Free bytes on the filesystem of `path`, or of its closest existing parent.
+fn free_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|dir| dir.exists())?;
+    let path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    // SAFETY: an all-zero statvfs is valid, statvfs only writes through the pointer
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a NUL-terminated string that outlives the call
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    // the field types differ between architectures
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stats.f_bavail) * u64::from(stats.f_frsize))
+}
+
+/// Whether `path` is on a tmpfs, which takes its room from memory.
+fn on_tmpfs(path: &Path) -> bool {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return false;
+    };
+    let path = path
+        .ancestors()
+        .find_map(|dir| dir.canonicalize().ok())
+        .unwrap_or_else(|| path.to_path_buf());
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some((fields.next()?, fields.next()?))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .is_some_and(|(_, fstype)| fstype == "tmpfs")
+}
+
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kilobytes: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches(" kB")
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Room a build in `dir` has. A tmpfs can only grow as far as memory allows.
+fn available(dir: &Path) -> Option<u64> {
+    let free = free_space(dir)?;
+    if on_tmpfs(dir) {
+        Some(available_memory().map_or(free, |memory| free.min(memory)))
+    } else {
+        Some(free)
+    }
+}
+
+/// Make sure a build fits in `dir` before starting it. Returns the directory
+/// to build in instead when it was relocated to `fallback`.
+pub fn check(dir: &Path, fallback: &Path, policy: LowSpace) -> Result<Option<PathBuf>> {
+    let Some(free) = available(dir) else {
+        log::debug!("could not tell how much room {:?} has", dir);
+        return Ok(None);
+    };
+    let tmpfs = if on_tmpfs(dir) { " (tmpfs)" } else { "" };
+    log::debug!("building in {:?}{tmpfs}, {} free", dir, format_size(free));
+    if free >= REQUIRED {
+        return Ok(None);
+    }
+    let no_room = || UpdateError::NoBuildSpace {
+        dir: dir.to_path_buf(),
+        free: format_size(free),
+        required: format_size(REQUIRED),
+    };
+    match policy {
+        LowSpace::Warn => {
+            log::warn!("{}", no_room());
+            Ok(None)
+        }
+        LowSpace::Fail => Err(no_room()),
+        LowSpace::Relocate => {
+            if available(fallback).is_none_or(|free| free < REQUIRED) {
+                log::warn!(
+                    "{:?}{tmpfs} only has {} free and {:?} isn't roomier, building in {:?} anyway",
+                    dir,
+                    format_size(free),
+                    fallback,
+                    dir
+                );
+                return Ok(None);
+            }
+            log::warn!(
+                "{:?}{tmpfs} only has {} free, building in {:?} instead",
+                dir,
+                format_size(free),
+                fallback
+            );
+            std::fs::create_dir_all(fallback)?;
+            Ok(Some(fallback.to_path_buf()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builddir_quoting() {
+        for contents in [
+            "BUILDDIR=/tmp/makepkg",
+            "BUILDDIR=\"/tmp/makepkg\"",
+            "BUILDDIR='/tmp/makepkg'",
+            "  BUILDDIR=/tmp/makepkg",
+        ] {
+            assert_eq!(
+                conf_builddir(contents, "/home/me").as_deref(),
+                Some("/tmp/makepkg"),
+                "{contents}"
+            );
+        }
+    }
+
+    #[test]
+    fn builddir_home() {
+        for contents in [
+            "BUILDDIR=~/build",
+            "BUILDDIR=$HOME/build",
+            "BUILDDIR=\"${HOME}/build\"",
+        ] {
+            assert_eq!(
+                conf_builddir(contents, "/home/me").as_deref(),
+                Some("/home/me/build"),
+                "{contents}"
+            );
+        }
+        // only a leading `~` is the home directory
+        assert_eq!(
+            conf_builddir("BUILDDIR=/tmp/a~b", "/home/me").as_deref(),
+            Some("/tmp/a~b")
+        );
+    }
+
+    #[test]
+    fn builddir_trailing_comment() {
+        assert_eq!(
+            conf_builddir("BUILDDIR=/tmp/makepkg # on tmpfs", "/home/me").as_deref(),
+            Some("/tmp/makepkg")
+        );
+        assert_eq!(
+            conf_builddir("BUILDDIR=\"/tmp/makepkg\"  # on tmpfs", "/home/me").as_deref(),
+            Some("/tmp/makepkg")
+        );
+    }
+
+    #[test]
+    fn builddir_last_assignment_wins() {
+        let contents = "\
+#-- Specify a directory for package building.
+#BUILDDIR=/tmp/commented
+BUILDDIR=/tmp/first
+OPTIONS=(strip docs)
+BUILDDIR=/tmp/second
+";
+        assert_eq!(
+            conf_builddir(contents, "/home/me").as_deref(),
+            Some("/tmp/second")
+        );
+    }
+
+    #[test]
+    fn builddir_unset() {
+        assert_eq!(
+            conf_builddir("#BUILDDIR=/tmp/makepkg\nPKGEXT='.pkg.tar.zst'", "/home/me"),
+            None
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use dialoguer::{Confirm, MultiSelect};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Result, UpdateError};
+use crate::history::History;
+use crate::term;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CacheArgs {
+    /// Show the disk usage of the cached packages and build trees (the default)
+    #[arg(long, default_value_t = false)]
+    size: bool,
+    /// Pick cached packages to delete
+    #[arg(long, default_value_t = false, conflicts_with = "size")]
+    prune: bool,
+}
+
+pub fn sha256(path: &Path) -> Result<String> {
+    let mut f = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match f.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Refuse a package that changed since it was built.
+pub fn verify(package: &Path, expected: &str) -> Result<()> {
+    let actual = sha256(package)?;
+    if actual != expected {
+        return Err(UpdateError::ChecksumMismatch {
+            path: package.to_path_buf(),
+            expected: expected.to_owned(),
+            actual,
+        });
+    }
+    log::debug!("{:?} matches its checksum", package);
+    Ok(())
+}
+
+fn cached_packages(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut packages = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(err) => return Err(err.into()),
+    };
+    packages.retain(|path| path.is_file());
+    packages.sort_by_key(|path| path.metadata().and_then(|m| m.modified()).ok());
+    Ok(packages)
+}
+
+/// Add `package`, whose checksum is `hash`, to the cache in `dir`. A package
+/// identical to a cached one isn't stored twice, and new ones are hard-linked
+/// when possible.
+pub fn store(package: &Path, hash: &str, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let size = package.metadata()?.len();
+    // only packages of the same size can be identical, the others aren't read
+    let same_size = cached_packages(dir)?
+        .into_iter()
+        .filter(|cached| cached.metadata().is_ok_and(|m| m.len() == size));
+    for cached in same_size {
+        if sha256(&cached)? == hash {
+            log::info!("the package is identical to the cached {:?}", cached);
+            return Ok(cached);
+        }
+    }
+    let name = package
+        .file_name()
+        .expect("package path has a file name")
+        .to_string_lossy();
+    let mut kept = dir.join(&*name);
+    if kept.exists() {
+        // a rebuild of the same version that came out different
+        kept = dir.join(format!("{}-{name}", &hash[..12]));
+    }
+    if std::fs::hard_link(package, &kept).is_err() {
+        std::fs::copy(package, &kept)?;
+    }
+    Ok(kept)
+}
+
+/// Disk usage of `path`, counting hard-linked files once.
+fn disk_usage(path: &Path, seen: &mut HashSet<(u64, u64)>) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if metadata.is_dir() {
+        std::fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| disk_usage(&entry.path(), seen))
+            .sum()
+    } else if seen.insert((metadata.dev(), metadata.ino())) {
+        metadata.blocks() * 512
+    } else {
+        0
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "K", "M", "G"] {
+        if size < 1024.0 {
+            return format!("{size:.1}{unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1}T")
+}
+
+/// History ids of the builds that produced `package`.
+fn builds_of(history: &History, package: &Path) -> String {
+    let ids = history
+        .entries
+        .iter()
+        .filter(|entry| entry.package.as_deref() == Some(package))
+        .map(|entry| entry.id.to_string())
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        "-".to_owned()
+    } else {
+        ids.join(",")
+    }
+}
+
+fn size(dir: &Path, history: &History, build_trees: &[&Path]) -> Result<()> {
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    for package in cached_packages(dir)? {
+        let usage = disk_usage(&package, &mut seen);
+        total += usage;
+        println!(
+            "{:>8} {:<10} {}",
+            format_size(usage),
+            builds_of(history, &package),
+            package.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    println!("{:>8} total in {}", format_size(total), dir.display());
+    for tree in build_trees.iter().filter(|tree| tree.exists()) {
+        println!(
+            "{:>8} {}",
+            format_size(disk_usage(tree, &mut seen)),
+            tree.display()
+        );
+    }
+    Ok(())
+}
+
+fn prune(dir: &Path, history: &History) -> Result<()> {
+    let packages = cached_packages(dir)?;
+    if packages.is_empty() {
+        log::info!("no cached packages");
+        return Ok(());
+    }
+    let items = packages
+        .iter()
+        .map(|package| {
+            format!(
+                "{} (builds {}, {})",
+                package.file_name().unwrap_or_default().to_string_lossy(),
+                builds_of(history, package),
+                format_size(package.metadata().map(|m| m.len()).unwrap_or(0))
+            )
+        })
+        .collect::<Vec<_>>();
+    if !term::interactive() {
+        return Err(UpdateError::NoTerminal("--prune".to_owned()));
+    }
+    let selected = MultiSelect::new()
+        .with_prompt("Packages to delete (space to select)")
+        .items(&items)
+        .interact()?;
+    if selected.is_empty() {
+        return Ok(());
+    }
+    let confirmed = Confirm::new()
+        .with_prompt(format!("Delete {} package(s)?", selected.len()))
+        .default(false)
+        .interact()?;
+    if confirmed {
+        for index in selected {
+            log::info!("deleting {:?}", packages[index]);
+            std::fs::remove_file(&packages[index])?;
+        }
+    }
+    Ok(())
+}
+
+pub fn cache(
+    args: &CacheArgs,
+    dir: &Path,
+    history_path: &Path,
+    build_trees: &[&Path],
+) -> Result<()> {
+    let history = History::load(history_path)?;
+    if args.prune {
+        prune(dir, &history)
+    } else {
+        size(dir, &history, build_trees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "qtile-git-0.29.0.r12.gabc1234-1-x86_64.pkg.tar.zst";
+
+    /// A built package named `name` in `dir`.
+    fn build(dir: &Path, name: &str, contents: &str) -> (PathBuf, String) {
+        std::fs::create_dir_all(dir).unwrap();
+        let package = dir.join(name);
+        std::fs::write(&package, contents).unwrap();
+        let hash = sha256(&package).unwrap();
+        (package, hash)
+    }
+
+    #[test]
+    fn new_packages_are_hard_linked() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("packages");
+        let (package, hash) = build(&dir.path().join("build"), NAME, "package");
+        let kept = store(&package, &hash, &cache).unwrap();
+        assert_eq!(kept, cache.join(NAME));
+        let (built, stored) = (package.metadata().unwrap(), kept.metadata().unwrap());
+        assert_eq!((built.dev(), built.ino()), (stored.dev(), stored.ino()));
+    }
+
+    #[test]
+    fn identical_packages_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("packages");
+        let (first, hash) = build(&dir.path().join("first"), NAME, "package");
+        let kept = store(&first, &hash, &cache).unwrap();
+        // the same package under another name, e.g. built with another pkgrel
+        let (second, hash) = build(
+            &dir.path().join("second"),
+            "qtile-git-0.29.0.r12.gabc1234-2-x86_64.pkg.tar.zst",
+            "package",
+        );
+        assert_eq!(store(&second, &hash, &cache).unwrap(), kept);
+        assert_eq!(cached_packages(&cache).unwrap(), [kept]);
+    }
+
+    #[test]
+    fn different_rebuilds_are_both_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("packages");
+        let (first, hash) = build(&dir.path().join("first"), NAME, "package");
+        let first_kept = store(&first, &hash, &cache).unwrap();
+        // same size, different contents
+        let (second, hash) = build(&dir.path().join("second"), NAME, "packagf");
+        let second_kept = store(&second, &hash, &cache).unwrap();
+        assert_ne!(first_kept, second_kept);
+        assert_eq!(second_kept, cache.join(format!("{}-{NAME}", &hash[..12])));
+        assert_eq!(std::fs::read_to_string(second_kept).unwrap(), "packagf");
+        assert_eq!(std::fs::read_to_string(first_kept).unwrap(), "package");
+    }
+}
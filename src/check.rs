@@ -0,0 +1,110 @@
+use regex::Regex;
+use subprocess::{Exec, PopenError};
+
+use crate::error::Result;
+use crate::git;
+use crate::self_update;
+use crate::status;
+
+/// Exit code of `check` when an update is available.
+pub const UPDATE_AVAILABLE: i32 = 100;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CheckArgs {
+    #[arg(
+        short,
+        long,
+        num_args = 1,
+        default_value = "qtile",
+        conflicts_with = "path"
+    )]
+    fork: Option<String>,
+    #[arg(short, long, num_args = 1, default_value = None)]
+    path: Option<String>,
+    /// Branch to compare the installed commit with
+    #[arg(short, long, default_value = "master")]
+    branch: String,
+    /// Send a desktop notification when an update is available
+    #[arg(long, default_value_t = false)]
+    notify: bool,
+}
+
+fn notify(body: &str) {
+    match Exec::cmd("notify-send")
+        .args(&["--app-name=update-qtile", "qtile update available", body])
+        .join()
+    {
+        Ok(_) => {}
+        Err(PopenError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            log::warn!("`notify-send` is not installed, can't send a notification");
+        }
+        Err(err) => log::warn!("could not send a notification: {err}"),
+    }
+}
+
+/// Compare the installed package with the remote, without cloning or
+/// building anything. Returns whether an update is available.
+pub fn check(args: &CheckArgs) -> Result<bool> {
+    let url = git::remote_url(args.fork.as_deref(), args.path.as_deref());
+    let refs = git::ls_remote(&url)?;
+    let installed = status::installed_version()?;
+    let installed_commit = installed.as_deref().and_then(|version| {
+        Some(
+            Regex::new(r"\.g([0-9a-f]{7,})")
+                .unwrap()
+                .captures(version)?[1]
+                .to_owned(),
+        )
+    });
+    let mut updates = vec![];
+
+    match refs
+        .iter()
+        .find(|r| r.branch() == Some(args.branch.as_str()))
+    {
+        Some(head) => {
+            let head = head.oid.to_string();
+            let up_to_date = installed_commit
+                .as_deref()
+                .is_some_and(|commit| head.starts_with(commit));
+            println!("{}: {:.10}", args.branch, head);
+            if !up_to_date {
+                updates.push(format!("new commits on {}", args.branch));
+            }
+        }
+        None => log::warn!("there is no branch `{}` in `{url}`", args.branch),
+    }
+
+    let installed_version = installed.as_deref().unwrap_or("0");
+    let newer_tags = refs
+        .iter()
+        .filter_map(|r| r.tag())
+        .filter(|tag| self_update::is_newer(tag, installed_version))
+        .collect::<Vec<_>>();
+    let (candidates, releases): (Vec<&str>, Vec<&str>) =
+        newer_tags.into_iter().partition(|tag| tag.contains("rc"));
+    for (kind, tags) in [("release", releases), ("release candidate", candidates)] {
+        if let Some(tag) = tags
+            .iter()
+            .max_by_key(|tag| self_update::parse_version(tag))
+        {
+            println!("{kind}: {tag}");
+            updates.push(format!("{kind} {tag}"));
+        }
+    }
+
+    match &installed {
+        Some(version) => println!("installed: {version}"),
+        None => println!("installed: qtile-git is not installed"),
+    }
+    if updates.is_empty() {
+        log::info!("qtile-git is up to date");
+        return Ok(false);
+    }
+    let summary = updates.join(", ");
+    log::info!("update available: {summary}");
+    if args.notify {
+        notify(&summary);
+    }
+    Ok(true)
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, UpdateError};
+use crate::process;
+
+/// Apply `timeout` to the network operations of libgit2.
+///
+/// This changes global state, so it must run before other threads use git.
+pub fn set_timeouts(timeout: Option<Duration>) -> Result<()> {
+    backend::set_timeouts(timeout)
+}
+
+/// Clone only the latest commit of `url` into `dest`, with libgit2 or, when
+/// built with the `gitoxide` feature, with gitoxide.
+///
+/// [`set_timeouts`] must have been called first.
+pub fn shallow_clone(url: &str, dest: &Path, timeout: Option<Duration>) -> Result<()> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    match backend::clone(url, dest, deadline) {
+        Ok(()) => Ok(()),
+        Err(_) if process::interrupted() => Err(UpdateError::Interrupted {
+            command: "git clone".to_owned(),
+        }),
+        Err(_) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+            Err(UpdateError::TimedOut {
+                command: "git clone".to_owned(),
+                seconds: timeout.unwrap_or_default().as_secs(),
+            })
+        }
+        Err(message) => Err(UpdateError::CloneFailed {
+            url: url.to_owned(),
+            message,
+        }),
+    }
+}
+
+#[cfg(not(feature = "gitoxide"))]
+mod backend {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use crate::error::Result as UpdateResult;
+    use crate::process;
+
+    pub fn set_timeouts(timeout: Option<Duration>) -> UpdateResult<()> {
+        let Some(timeout) = timeout else {
+            return Ok(());
+        };
+        let millis = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        // SAFETY: called before the threads that use libgit2 are spawned
+        unsafe {
+            git2::opts::set_server_connect_timeout_in_milliseconds(millis)?;
+            git2::opts::set_server_timeout_in_milliseconds(millis)?;
+        }
+        Ok(())
+    }
+
+    pub fn clone(url: &str, dest: &Path, deadline: Option<Instant>) -> Result<(), String> {
+        let mut callbacks = git2::RemoteCallbacks::new();
+        callbacks.transfer_progress(move |_| {
+            !process::interrupted() && deadline.is_none_or(|deadline| Instant::now() < deadline)
+        });
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks).depth(1);
+        git2::build::RepoBuilder::new()
+            .fetch_options(fetch_options)
+            .clone(url, dest)
+            .map(drop)
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(feature = "gitoxide")]
+mod backend {
+    use std::num::NonZeroU32;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use gix::progress::prodash::messages::{MessageCopyState, MessageLevel};
+    use gix::progress::tree::Root;
+
+    use crate::error::Result as UpdateResult;
+    use crate::process;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+    /// Timeouts are enforced by the watcher in [`clone`].
+    pub fn set_timeouts(_timeout: Option<Duration>) -> UpdateResult<()> {
+        Ok(())
+    }
+
+    fn checkout(
+        url: &str,
+        dest: &Path,
+        cancel: &AtomicBool,
+        progress: &Root,
+    ) -> Result<(), String> {
+        let depth = NonZeroU32::new(1).expect("1 is not zero");
+        let (mut checkout, _) = gix::prepare_clone(url, dest)
+            .map_err(|err| err.to_string())?
+            .with_shallow(gix::remote::fetch::Shallow::DepthAtRemote(depth))
+            .fetch_then_checkout(progress.add_child("fetch"), cancel)
+            .map_err(|err| err.to_string())?;
+        checkout
+            .main_worktree(progress.add_child("checkout"), cancel)
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// Log the messages gitoxide reported since `previous`.
+    fn log_progress(progress: &Root, previous: Option<MessageCopyState>) -> MessageCopyState {
+        let mut messages = vec![];
+        let state = progress.copy_new_messages(&mut messages, previous);
+        for message in messages {
+            match message.level {
+                MessageLevel::Failure => log::warn!("{}: {}", message.origin, message.message),
+                MessageLevel::Info | MessageLevel::Success => {
+                    log::info!("{}: {}", message.origin, message.message);
+                }
+            }
+        }
+        state
+    }
+
+    /// gitoxide only checks a cancellation flag, which a watcher thread sets on
+    /// interrupt or once the deadline has passed. The same thread logs the
+    /// progress messages of the clone as they come in.
+    pub fn clone(url: &str, dest: &Path, deadline: Option<Instant>) -> Result<(), String> {
+        let cancel = AtomicBool::new(false);
+        let done = AtomicBool::new(false);
+        let progress: Arc<Root> = Root::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut state = None;
+                while !done.load(Ordering::SeqCst) {
+                    state = Some(log_progress(&progress, state));
+                    if process::interrupted()
+                        || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                    {
+                        cancel.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                log_progress(&progress, state);
+            });
+            let result = checkout(url, dest, &cancel, &progress);
+            done.store(true, Ordering::SeqCst);
+            result
+        })
+    }
+}
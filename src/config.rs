@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde_json::Value;
+use subprocess::Exec;
+
+use crate::build_space;
+use crate::error::{Result, UpdateError};
+use crate::escalate::Escalate;
+use crate::pkgbuild;
+use crate::process;
+use crate::sessions;
+use crate::stage::Stage;
+use crate::term;
+use crate::xdg;
+use crate::UpdateArgs;
+
+pub fn config_path() -> PathBuf {
+    xdg::config_home().join("update-qtile").join("config.toml")
+}
+
+/// A named set of `update` options. Keys are the long option names.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub fork: Option<String>,
+    pub path: Option<String>,
+    pub commit: Option<String>,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub restart: Option<bool>,
+    pub restart_when_idle: Option<u64>,
+    pub restart_on_login: Option<bool>,
+    pub preserve_layout: Option<bool>,
+    pub escalate: Option<Escalate>,
+    pub build_user: Option<String>,
+    pub keep_build: Option<bool>,
+    pub no_file_diff: Option<bool>,
+    pub lint: Option<bool>,
+    pub wlroots: Option<String>,
+    pub version_source: Option<String>,
+    pub add_depends: Option<Vec<String>>,
+    pub add_makedepends: Option<Vec<String>>,
+    pub allow_unpatched: Option<bool>,
+    pub benchmark: Option<bool>,
+    pub benchmark_config: Option<PathBuf>,
+    pub no_verify: Option<bool>,
+    pub allow_unverified: Option<bool>,
+    pub valid_keys: Option<Vec<String>>,
+    pub keyring: Option<PathBuf>,
+    pub packager: Option<String>,
+    pub backend: Option<sessions::Backend>,
+    pub makepkg_conf: Option<PathBuf>,
+    pub low_space: Option<build_space::LowSpace>,
+    /// Stage timeouts, e.g. `timeout = { build = "30m" }`.
+    pub timeout: Option<BTreeMap<Stage, String>>,
+    pub wait_for_pacman: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Profile used when `--profile` isn't given.
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl Profile {
+    fn validate(&self) -> std::result::Result<(), String> {
+        // the command line rejects these combinations too
+        let targets = [
+            ("commit", &self.commit),
+            ("branch", &self.branch),
+            ("tag", &self.tag),
+        ]
+        .into_iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(id, _)| format!("`{id}`"))
+        .collect::<Vec<_>>();
+        if targets.len() > 1 {
+            return Err(format!("{} can't be set together", targets.join(" and ")));
+        }
+        if self.fork.is_some() && self.path.is_some() {
+            return Err("`fork` and `path` can't be set together".to_owned());
+        }
+        if let Some(version) = &self.wlroots {
+            pkgbuild::parse_wlroots_version(version)?;
+        }
+        if let Some(version_source) = &self.version_source {
+            pkgbuild::parse_version_source(version_source)?;
+        }
+        for duration in self
+            .timeout
+            .iter()
+            .flat_map(BTreeMap::values)
+            .chain(&self.wait_for_pacman)
+        {
+            process::parse_duration(duration)?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents, path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parse and check a config, `path` is only used in errors.
+    pub fn parse(contents: &str, path: &Path) -> Result<Self> {
+        let invalid = |message| UpdateError::InvalidConfig {
+            path: path.to_path_buf(),
+            message,
+        };
+        let config: Self = toml::from_str(contents).map_err(|err| invalid(err.to_string()))?;
+        if let Some(name) = &config.default_profile {
+            if !config.profile.contains_key(name) {
+                return Err(invalid(format!("default profile `{name}` doesn't exist")));
+            }
+        }
+        for (name, profile) in &config.profile {
+            profile
+                .validate()
+                .map_err(|message| invalid(format!("profile `{name}`: {message}")))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Whether the user set `id` on the command line or through the environment.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// Fill in the options of the selected profile that weren't given on the
+/// command line. Options that exclude each other are taken together, so a
+/// `--commit` on the command line overrides a profile's `branch`.
+///
+/// Returns the name of the profile and the options taken from it.
+pub fn apply_profile(
+    args: &mut UpdateArgs,
+    matches: &ArgMatches,
+) -> Result<Option<(String, Vec<&'static str>)>> {
+    let path = config_path();
+    let mut config = Config::load(&path)?;
+    let Some(name) = args.profile.clone().or(config.default_profile) else {
+        return Ok(None);
+    };
+    let Some(profile) = config.profile.remove(&name) else {
+        return Err(UpdateError::UnknownProfile { name, path });
+    };
+    log::info!("using profile `{name}`");
+    let applied = apply(profile, args, matches, &path)?;
+    Ok(Some((name, applied)))
+}
+
+/// Fill in the options of `profile` that weren't given on the command line,
+/// `path` is only used in errors.
+fn apply(
+    profile: Profile,
+    args: &mut UpdateArgs,
+    matches: &ArgMatches,
+    path: &Path,
+) -> Result<Vec<&'static str>> {
+    let mut applied = vec![];
+
+    let any_given = |ids: &[&str]| ids.iter().any(|id| given(matches, id));
+    if !any_given(&["fork", "path"]) {
+        if profile.path.is_some() {
+            args.fork = None;
+            args.path = profile.path;
+            applied.extend(["fork", "path"]);
+        } else if profile.fork.is_some() {
+            args.fork = profile.fork;
+            applied.push("fork");
+        }
+    }
+    if !any_given(&[
+        "commit",
+        "branch",
+        "tag",
+        "include_dirty",
+        "interactive",
+        "resume",
+    ]) {
+        for (id, value) in [
+            ("commit", &profile.commit),
+            ("branch", &profile.branch),
+            ("tag", &profile.tag),
+        ] {
+            if value.is_some() {
+                applied.push(id);
+            }
+        }
+        args.commit = profile.commit.or(args.commit.take());
+        args.branch = profile.branch.or(args.branch.take());
+        args.tag = profile.tag.or(args.tag.take());
+    }
+    if !any_given(&["restart", "restart_on_login"]) {
+        for (id, value) in [
+            ("restart", profile.restart),
+            ("restart_on_login", profile.restart_on_login),
+        ] {
+            if value.is_some() {
+                applied.push(id);
+            }
+        }
+        args.restart = profile.restart.unwrap_or(args.restart);
+        args.restart_on_login = profile.restart_on_login.unwrap_or(args.restart_on_login);
+    }
+
+    macro_rules! option {
+        ($($field:ident),*) => {$(
+            if !given(matches, stringify!($field)) && profile.$field.is_some() {
+                args.$field = profile.$field;
+                applied.push(stringify!($field));
+            }
+        )*};
+    }
+    macro_rules! value {
+        ($($field:ident),*) => {$(
+            if let Some(value) = profile.$field.filter(|_| !given(matches, stringify!($field))) {
+                args.$field = value;
+                applied.push(stringify!($field));
+            }
+        )*};
+    }
+    option!(
+        restart_when_idle,
+        escalate,
+        build_user,
+        makepkg_conf,
+        benchmark_config,
+        keyring,
+        packager,
+        backend,
+        version_source
+    );
+    value!(
+        preserve_layout,
+        keep_build,
+        no_file_diff,
+        lint,
+        add_depends,
+        add_makedepends,
+        allow_unpatched,
+        benchmark,
+        no_verify,
+        allow_unverified,
+        valid_keys,
+        low_space
+    );
+
+    let invalid = |message| UpdateError::InvalidConfig {
+        path: path.to_path_buf(),
+        message,
+    };
+    if let Some(version) = profile.wlroots.filter(|_| !given(matches, "wlroots")) {
+        args.wlroots = Some(pkgbuild::parse_wlroots_version(&version).map_err(invalid)?);
+        applied.push("wlroots");
+    }
+    if let Some(timeouts) = profile.timeout.filter(|_| !given(matches, "timeouts")) {
+        args.timeouts = timeouts
+            .into_iter()
+            .map(|(stage, duration)| Ok((stage, process::parse_duration(&duration)?)))
+            .collect::<std::result::Result<_, String>>()
+            .map_err(invalid)?;
+        applied.push("timeouts");
+    }
+    if let Some(duration) = profile
+        .wait_for_pacman
+        .filter(|_| !given(matches, "wait_for_pacman"))
+    {
+        args.wait_for_pacman = Some(process::parse_duration(&duration).map_err(invalid)?);
+        applied.push("wait_for_pacman");
+    }
+    Ok(applied)
+}
+
+pub fn serialize_timeouts<S: serde::Serializer>(
+    timeouts: &[(Stage, Duration)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        timeouts
+            .iter()
+            .map(|(stage, timeout)| (stage.name(), format!("{}s", timeout.as_secs()))),
+    )
+}
+
+pub fn serialize_duration<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_some(&format!("{}s", duration.as_secs())),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Print every `update` option with its value and where the value came from.
+pub fn show(mut args: UpdateArgs, matches: &ArgMatches) -> Result<()> {
+    let profile = apply_profile(&mut args, matches)?;
+    let applied = profile
+        .as_ref()
+        .map(|(_, applied)| applied.as_slice())
+        .unwrap_or_default();
+    println!("config file: {}", config_path().display());
+    let values = serde_json::to_value(&args)?;
+    let Value::Object(values) = values else {
+        unreachable!("UpdateArgs serializes to a map");
+    };
+    for (id, value) in values {
+        let origin = if applied.contains(&id.as_str()) {
+            let (name, _) = profile
+                .as_ref()
+                .expect("options were applied from a profile");
+            format!("profile `{name}`")
+        } else {
+            match matches.value_source(&id) {
+                Some(ValueSource::CommandLine) => "command line".to_owned(),
+                Some(ValueSource::EnvVariable) => "environment".to_owned(),
+                _ => "default".to_owned(),
+            }
+        };
+        println!(
+            "{:<20} {:<30} {origin}",
+            id.replace('_', "-"),
+            value.to_string()
+        );
+    }
+    Ok(())
+}
+
+fn editor() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned())
+}
+
+/// Edit a copy of the config and only save it once it is valid.
+pub fn edit() -> Result<()> {
+    let path = config_path();
+    let original = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let draft = tempfile::Builder::new().suffix(".toml").tempfile()?;
+    std::fs::write(draft.path(), &original)?;
+    loop {
+        // the editor may be a command with arguments, e.g. `code --wait`
+        let status = Exec::shell(format!("{} \"$1\"", editor()))
+            .arg("update-qtile")
+            .arg(draft.path())
+            .join()?;
+        if !status.success() {
+            log::warn!("the editor failed, the config was not changed");
+            return Ok(());
+        }
+        let contents = std::fs::read_to_string(draft.path())?;
+        match Config::parse(&contents, &path) {
+            Ok(_) if contents == original => {
+                log::info!("the config was not changed");
+                return Ok(());
+            }
+            Ok(_) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, contents)?;
+                log::info!("saved {:?}", path);
+                return Ok(());
+            }
+            Err(err) => {
+                log::error!("{err}");
+                if !term::confirm("Would you like to edit it again?") {
+                    log::warn!("the config was not changed");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::Cli;
+
+    fn parse(contents: &str) -> Result<Config> {
+        Config::parse(contents, Path::new("config.toml"))
+    }
+
+    /// Apply `profile` under the command line `cli`.
+    fn update(cli: &[&str], profile: &str) -> (UpdateArgs, Vec<&'static str>) {
+        let matches = Cli::command()
+            .get_matches_from(std::iter::once("update-qtile").chain(cli.iter().copied()));
+        let mut args = Cli::from_arg_matches(&matches).unwrap().update;
+        let mut config = parse(&format!("[profile.test]\n{profile}")).unwrap();
+        let profile = config.profile.remove("test").unwrap();
+        let applied = apply(profile, &mut args, &matches, Path::new("config.toml")).unwrap();
+        (args, applied)
+    }
+
+    #[test]
+    fn valid() {
+        let config = parse(
+            r#"
+default-profile = "laptop"
+
+[profile.laptop]
+fork = "me"
+branch = "wayland"
+wlroots = "0.17"
+timeout = { build = "30m" }
+
+[profile.stable]
+tag = "v0.29.0"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.default_profile.as_deref(), Some("laptop"));
+        assert_eq!(config.profile.len(), 2);
+        assert_eq!(config.profile["stable"].tag.as_deref(), Some("v0.29.0"));
+    }
+
+    #[test]
+    fn empty() {
+        let config = parse("").unwrap();
+        assert!(config.default_profile.is_none());
+        assert!(config.profile.is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        for contents in [
+            "[profile.a]\nbogus = true",
+            "verbose = true",
+            "default-profile = \"missing\"",
+            "[profile.a]\nwlroots = \"latest\"",
+            "[profile.a]\ntimeout = { build = \"soon\" }",
+            "[profile.a]\nwait-for-pacman = \"-1s\"",
+            "[profile.a]\nversion-source = \"elsewhere\"",
+            "[profile.a]\ncommit = \"abc\"\nbranch = \"master\"",
+            "[profile.a]\nbranch = \"master\"\ntag = \"v0.29.0\"",
+            "[profile.a]\nfork = \"me\"\npath = \"/src/qtile\"",
+        ] {
+            assert!(parse(contents).is_err(), "{contents}");
+        }
+    }
+
+    #[test]
+    fn profile_fills_in_defaults() {
+        let (args, applied) = update(&[], "branch = \"wayland\"\nkeep-build = true");
+        assert_eq!(args.branch.as_deref(), Some("wayland"));
+        assert!(args.keep_build);
+        assert_eq!(applied, ["branch", "keep_build"]);
+    }
+
+    #[test]
+    fn command_line_overrides_profile() {
+        let (args, applied) = update(&["--no-file-diff"], "no-file-diff = false");
+        assert!(args.no_file_diff);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn command_line_target_overrides_profile_target() {
+        let (args, applied) = update(&["--commit", "abc1234"], "branch = \"wayland\"");
+        assert_eq!(args.commit.as_deref(), Some("abc1234"));
+        assert_eq!(args.branch, None);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn command_line_fork_overrides_profile_path() {
+        let (args, applied) = update(&["--fork", "me"], "path = \"/src/qtile\"");
+        assert_eq!(args.fork.as_deref(), Some("me"));
+        assert_eq!(args.path, None);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn profile_path_replaces_default_fork() {
+        let (args, applied) = update(&[], "path = \"/src/qtile\"");
+        assert_eq!(args.fork, None);
+        assert_eq!(args.path.as_deref(), Some("/src/qtile"));
+        assert_eq!(applied, ["fork", "path"]);
+    }
+}
@@ -0,0 +1,88 @@
+use regex::Regex;
+use subprocess::{Exec, Redirection};
+
+/// A file pacman refused to overwrite.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    /// The package the file belongs to, `None` for leftovers no package owns.
+    pub owner: Option<String>,
+}
+
+/// Conflicting files from the output of a failed `pacman -U`.
+pub fn file_conflicts(log: &str) -> Vec<Conflict> {
+    let conflict =
+        Regex::new(r"^\S+: (/.+?) exists in filesystem(?: \(owned by (\S+)\))?$").unwrap();
+    log.lines()
+        .filter_map(|line| conflict.captures(line.trim()))
+        .map(|captures| {
+            let path = captures[1].to_owned();
+            let owner = match captures.get(2) {
+                Some(owner) => Some(owner.as_str().to_owned()),
+                None => owner(&path),
+            };
+            Conflict { path, owner }
+        })
+        .collect()
+}
+
+/// The installed package owning `path`.
+fn owner(path: &str) -> Option<String> {
+    Exec::cmd("pacman")
+        .args(&["-Qoq", path])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .ok()
+        .filter(|capture| capture.success())
+        .map(|capture| capture.stdout_str().trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicts() {
+        let log = "\
+loading packages...
+resolving dependencies...
+looking for conflicting packages...
+(1/1) checking for file conflicts                  [######################] 100%
+error: failed to commit transaction (conflicting files)
+qtile-git: /usr/bin/qtile exists in filesystem (owned by qtile)
+qtile-git: /usr/share/licenses/qtile-git/LICENSE exists in filesystem (owned by qtile)
+Errors occurred, no packages were upgraded.
+";
+        let conflicts = file_conflicts(log);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].path, "/usr/bin/qtile");
+        assert_eq!(conflicts[0].owner.as_deref(), Some("qtile"));
+        assert_eq!(conflicts[1].path, "/usr/share/licenses/qtile-git/LICENSE");
+    }
+
+    #[test]
+    fn paths_with_spaces() {
+        let log =
+            "qtile-git: /usr/share/qtile/my layout.py exists in filesystem (owned by qtile)\n";
+        let conflicts = file_conflicts(log);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/usr/share/qtile/my layout.py");
+    }
+
+    #[test]
+    fn unowned_leftovers() {
+        // no package owns a path that doesn't exist
+        let log = "qtile-git: /nonexistent/update-qtile/test.py exists in filesystem\n";
+        let conflicts = file_conflicts(log);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/nonexistent/update-qtile/test.py");
+        assert_eq!(conflicts[0].owner, None);
+    }
+
+    #[test]
+    fn no_conflicts() {
+        let log = "error: failed to prepare transaction (could not satisfy dependencies)\n";
+        assert!(file_conflicts(log).is_empty());
+    }
+}
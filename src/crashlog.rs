@@ -0,0 +1,153 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::process;
+use crate::xdg;
+
+/// How long the log is watched after a restart.
+pub const WATCH: Duration = Duration::from_secs(20);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracebacks that have a known fix: (pattern, suggestion).
+const KNOWN: [(&str, &str); 5] = [
+    (
+        r"qtile_extras",
+        "rebuild qtile-extras against the new qtile",
+    ),
+    (
+        r"pywlroots|wlroots",
+        "rebuild python-pywlroots, or pin it with --wlroots",
+    ),
+    (
+        r"ImportError: cannot import name .* from 'libqtile",
+        "something your config imports was moved or removed, check the changelog",
+    ),
+    (
+        r"ModuleNotFoundError: No module named '([^']+)'",
+        "install the Python package providing `$1`",
+    ),
+    (
+        r"SyntaxError|Error in config|Config file could not be loaded",
+        "fix your config, qtile is running the default one",
+    ),
+];
+
+pub fn log_path() -> PathBuf {
+    xdg::data_home().join("qtile").join("qtile.log")
+}
+
+/// Current size of the log, where the new output will start.
+pub fn log_len(path: &Path) -> u64 {
+    path.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+fn read_from(path: &Path, offset: u64) -> String {
+    let mut contents = String::new();
+    if let Ok(mut f) = std::fs::File::open(path) {
+        // the log was rotated if it shrank
+        let offset = if log_len(path) < offset { 0 } else { offset };
+        let _ = f.seek(SeekFrom::Start(offset));
+        let _ = f.read_to_string(&mut contents);
+    }
+    contents
+}
+
+/// Tracebacks in `log`, each running from its header to the exception line.
+fn tracebacks(log: &str) -> Vec<String> {
+    let mut found = vec![];
+    let mut current: Option<Vec<&str>> = None;
+    for line in log.lines() {
+        if line.contains("Traceback (most recent call last)") {
+            current = Some(vec![line]);
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+            if !line.starts_with(' ') {
+                found.push(lines.join("\n"));
+                current = None;
+            }
+        }
+    }
+    found
+}
+
+/// What to do about a traceback, if it is a known one.
+pub fn suggestion(traceback: &str) -> Option<String> {
+    KNOWN.iter().find_map(|(pattern, suggestion)| {
+        let captures = Regex::new(pattern).unwrap().captures(traceback)?;
+        let mut expanded = String::new();
+        captures.expand(suggestion, &mut expanded);
+        Some(expanded)
+    })
+}
+
+/// Watch the log from `offset` for [`WATCH`] and return the tracebacks
+/// written in that time.
+pub fn watch(path: &Path, offset: u64) -> Vec<String> {
+    log::info!("watching {:?} for errors", path);
+    let start = Instant::now();
+    while start.elapsed() < WATCH && !process::interrupted() {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    tracebacks(&read_from(path, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceback() {
+        let log = "\
+2026-10-16 10:00:00,000 WARNING libqtile manager.py:load_config():L107 Configuration error:
+Traceback (most recent call last):
+  File \"/usr/lib/python3.12/site-packages/libqtile/confreader.py\", line 140, in load
+    config = __import__(name)
+             ^^^^^^^^^^^^^^^^
+ModuleNotFoundError: No module named 'qtile_extras'
+2026-10-16 10:00:01,000 INFO libqtile manager.py:__init__():L90 Starting qtile
+";
+        assert_eq!(
+            tracebacks(log),
+            [log.lines().skip(1).take(5).collect::<Vec<_>>().join("\n")]
+        );
+    }
+
+    #[test]
+    fn several_tracebacks() {
+        let log = "\
+Traceback (most recent call last):
+  File \"a.py\", line 1, in <module>
+ValueError: first
+Traceback (most recent call last):
+  File \"b.py\", line 2, in <module>
+KeyError: 'second'
+";
+        let found = tracebacks(log);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].ends_with("ValueError: first"));
+        assert!(found[1].ends_with("KeyError: 'second'"));
+    }
+
+    #[test]
+    fn incomplete_traceback() {
+        let log = "Traceback (most recent call last):\n  File \"a.py\", line 1\n";
+        assert!(tracebacks(log).is_empty());
+        assert!(tracebacks("no errors here\n").is_empty());
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(
+            suggestion("ModuleNotFoundError: No module named 'psutil'").as_deref(),
+            Some("install the Python package providing `psutil`")
+        );
+        assert_eq!(
+            suggestion("ImportError: cannot import name 'foo' from 'libqtile.widget'").as_deref(),
+            Some("something your config imports was moved or removed, check the changelog")
+        );
+        assert_eq!(suggestion("ZeroDivisionError: division by zero"), None);
+    }
+}
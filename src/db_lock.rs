@@ -0,0 +1,53 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, UpdateError};
+use crate::process;
+
+/// Held by pacman while it changes the package database.
+const DB_LOCK: &str = "/var/lib/pacman/db.lck";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Make sure no other pacman holds the database lock before installing.
+///
+/// Without `timeout` a held lock is an error right away, otherwise wait for it
+/// to be released for at most `timeout`.
+pub fn wait_unlocked(timeout: Option<Duration>) -> Result<()> {
+    let lock = Path::new(DB_LOCK);
+    if !lock.exists() {
+        return Ok(());
+    }
+    let locked = || UpdateError::PacmanLocked {
+        path: lock.to_path_buf(),
+    };
+    let Some(timeout) = timeout else {
+        return Err(locked());
+    };
+    log::info!(
+        "the pacman database is locked, waiting up to {}s for it to be released",
+        timeout.as_secs()
+    );
+    let start = Instant::now();
+    let mut last_report = start;
+    while lock.exists() {
+        if process::interrupted() {
+            return Err(UpdateError::Interrupted {
+                command: "waiting for pacman".to_owned(),
+            });
+        }
+        if start.elapsed() >= timeout {
+            return Err(locked());
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            log::info!(
+                "still waiting for pacman ({}s elapsed)",
+                start.elapsed().as_secs()
+            );
+            last_report = Instant::now();
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    log::info!("the pacman database was released");
+    Ok(())
+}
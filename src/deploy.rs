@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use crate::cache;
+use crate::error::{Result, UpdateError};
+use crate::escalate::Escalate;
+use crate::history::{self, History, Outcome};
+
+/// Restart every qtile of the remote user, whatever display it runs on.
+const REMOTE_RESTART: &str = r#"for socket in "${XDG_CACHE_HOME:-$HOME/.cache}"/qtile/qtilesocket.*; do qtile cmd-obj -s "$socket" -o cmd -f restart; done"#;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DeployArgs {
+    /// Host to install on, as accepted by ssh, e.g. `user@host` (repeatable)
+    #[arg(long = "host", value_name = "HOST", required = true)]
+    hosts: Vec<String>,
+    /// History id of the build to deploy, the latest successful one by default
+    #[arg(long, value_name = "ID")]
+    build: Option<u64>,
+    /// How to run pacman as root on the hosts
+    #[arg(long, value_enum, default_value = "sudo")]
+    escalate: Escalate,
+    /// Restart qtile on the hosts after installing
+    #[arg(short, long, default_value_t = false)]
+    restart: bool,
+}
+
+fn step(host: &str, name: &str, exec: Exec) -> Result<()> {
+    log::info!("{host}: {name}");
+    if exec.join()?.success() {
+        Ok(())
+    } else {
+        Err(UpdateError::DeployFailed {
+            host: host.to_owned(),
+            step: name.to_owned(),
+        })
+    }
+}
+
+/// Create a directory only the SSH user of `host` can write to, so nobody else
+/// can swap the package before pacman installs it as root.
+fn remote_temp_dir(host: &str) -> Result<String> {
+    let name = "creating a private directory";
+    log::info!("{host}: {name}");
+    let capture = Exec::cmd("ssh")
+        .args(&[host, "mktemp", "-d", "/tmp/update-qtile.XXXXXXXXXX"])
+        .stdout(Redirection::Pipe)
+        .capture()?;
+    let dir = capture.stdout_str().trim().to_owned();
+    if capture.success() && dir.starts_with('/') {
+        Ok(dir)
+    } else {
+        Err(UpdateError::DeployFailed {
+            host: host.to_owned(),
+            step: name.to_owned(),
+        })
+    }
+}
+
+fn deploy_to(host: &str, package: &Path, sha256: Option<&str>, args: &DeployArgs) -> Result<()> {
+    let name = package
+        .file_name()
+        .and_then(|name| name.to_str())
+        .expect("cached packages have UTF-8 names");
+    let dir = remote_temp_dir(host)?;
+    let remote = format!("{dir}/{name}");
+    let copied = step(
+        host,
+        "copying package",
+        Exec::cmd("scp")
+            .arg("-q")
+            .arg(package)
+            .arg(format!("{host}:{remote}")),
+    );
+    let verified = copied.and_then(|()| match sha256 {
+        Some(sha256) => step(
+            host,
+            "verifying package",
+            Exec::cmd("ssh").args(&[
+                host,
+                &format!("echo '{sha256}  {remote}' | sha256sum --check --quiet"),
+            ]),
+        ),
+        None => Ok(()),
+    });
+    let installed = verified.and_then(|()| {
+        step(
+            host,
+            "installing package",
+            Exec::cmd("ssh").args(&[
+                "-t",
+                host,
+                args.escalate.program(),
+                "pacman",
+                "-U",
+                "--noconfirm",
+                &remote,
+            ]),
+        )
+    });
+    let _ = Exec::cmd("ssh").args(&[host, "rm", "-rf", &dir]).join();
+    installed?;
+    if args.restart {
+        step(
+            host,
+            "restarting qtile",
+            Exec::cmd("ssh").args(&[host, REMOTE_RESTART]),
+        )?;
+    }
+    Ok(())
+}
+
+/// Install a package built here on other machines over SSH.
+pub fn deploy(args: &DeployArgs, cache_home: &Path) -> Result<()> {
+    let history = History::load(&history::history_path(cache_home))?;
+    let entry = match args.build {
+        Some(id) => history.entries.iter().find(|entry| entry.id == id),
+        None => history
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.outcome == Outcome::Success && entry.package.is_some()),
+    };
+    let Some(entry) = entry else {
+        return Err(match args.build {
+            Some(id) => UpdateError::NoSuchBuild(id),
+            None => UpdateError::PackageNotFound {
+                dir: history::packages_dir(cache_home),
+            },
+        });
+    };
+    let Some(package) = entry.package.as_deref().filter(|package| package.exists()) else {
+        return Err(UpdateError::PackageNotFound {
+            dir: history::packages_dir(cache_home),
+        });
+    };
+    match &entry.sha256 {
+        Some(sha256) => cache::verify(package, sha256)?,
+        None => log::warn!("build {} has no checksum, not verifying it", entry.id),
+    }
+    log::info!("deploying {:?} (build {})", package, entry.id);
+
+    let mut failed = vec![];
+    for host in &args.hosts {
+        if let Err(err) = deploy_to(host, package, entry.sha256.as_deref(), args) {
+            log::error!("{err}");
+            failed.push(host.as_str());
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(UpdateError::DeployFailed {
+            host: failed.join(", "),
+            step: "deploy".to_owned(),
+        })
+    }
+}
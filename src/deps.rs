@@ -0,0 +1,121 @@
+use regex::Regex;
+use subprocess::{Exec, Redirection};
+
+/// Packages a failed makepkg run complained about, from its log.
+pub fn missing_dependencies(log: &str) -> Vec<String> {
+    let binary = Regex::new(r"Cannot find the (\S+) binary").unwrap();
+    let not_found = Regex::new(r"error: target not found: (\S+)").unwrap();
+    let mut missing: Vec<String> = vec![];
+    let mut in_list = false;
+    for line in log.lines() {
+        if line.contains("Missing dependencies:") {
+            in_list = true;
+            continue;
+        }
+        if in_list {
+            match line.trim().strip_prefix("-> ") {
+                Some(dep) => missing.push(strip_version(dep).to_owned()),
+                None => in_list = false,
+            }
+        }
+        if let Some(captures) = binary.captures(line) {
+            missing.push(package_providing(&captures[1]));
+        }
+        if let Some(captures) = not_found.captures(line) {
+            missing.push(strip_version(&captures[1]).to_owned());
+        }
+    }
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+pub fn strip_version(dep: &str) -> &str {
+    dep.split(['<', '>', '=']).next().unwrap_or(dep)
+}
+
+/// The package shipping `/usr/bin/<binary>`, falling back to the binary name.
+fn package_providing(binary: &str) -> String {
+    Exec::cmd("pacman")
+        .args(&["-Fq", &format!("/usr/bin/{binary}")])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .ok()
+        .filter(|capture| capture.success())
+        .and_then(|capture| {
+            let stdout = capture.stdout_str();
+            let package = stdout.lines().next()?;
+            // `pacman -Fq` prints `repo/package`
+            Some(package.rsplit('/').next().unwrap_or(package).to_owned())
+        })
+        .unwrap_or_else(|| binary.to_owned())
+}
+
+/// Whether `package` is available from the sync repos (as opposed to the AUR).
+pub fn in_repos(package: &str) -> bool {
+    Exec::cmd("pacman")
+        .args(&["-Si", package])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .join()
+        .is_ok_and(|status| status.success())
+}
+
+/// The first AUR helper found in `PATH`.
+pub fn aur_helper() -> Option<&'static str> {
+    ["paru", "yay"]
+        .into_iter()
+        .find(|helper| crate::escalate::in_path(helper))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_from_dependency_check() {
+        let log = "\
+==> Making package: qtile-git 0.29.0.r12.gabc1234-1 (Thu 16 Oct 2026 10:00:00 UTC)
+==> Checking runtime dependencies...
+==> Missing dependencies:
+  -> python-cairocffi
+  -> python-xcffib>=1.4.0
+==> Checking buildtime dependencies...
+==> Missing dependencies:
+  -> python-setuptools-scm
+==> ERROR: Could not resolve all dependencies.
+";
+        assert_eq!(
+            missing_dependencies(log),
+            ["python-cairocffi", "python-setuptools-scm", "python-xcffib"]
+        );
+    }
+
+    #[test]
+    fn missing_from_failed_sync() {
+        let log = "\
+==> Installing missing dependencies...
+error: target not found: python-pywlroots>=0.17
+==> ERROR: 'pacman' failed to install missing dependencies.
+";
+        assert_eq!(missing_dependencies(log), ["python-pywlroots"]);
+    }
+
+    #[test]
+    fn nothing_missing() {
+        let log = "\
+==> Checking runtime dependencies...
+==> Checking buildtime dependencies...
+==> Retrieving sources...
+";
+        assert!(missing_dependencies(log).is_empty());
+    }
+
+    #[test]
+    fn strips_versions() {
+        assert_eq!(strip_version("python>=3.12"), "python");
+        assert_eq!(strip_version("wlroots0.17=0.17.4"), "wlroots0.17");
+        assert_eq!(strip_version("cairo"), "cairo");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use crate::error::{Result, UpdateError};
+use crate::files;
+use crate::git;
+use crate::history::{self, Entry, History};
+use crate::term;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiffBuildsArgs {
+    /// History id of the older build
+    old: u64,
+    /// History id of the newer build
+    new: u64,
+}
+
+fn find(history: &History, id: u64) -> Result<&Entry> {
+    history
+        .entries
+        .iter()
+        .find(|entry| entry.id == id)
+        .ok_or(UpdateError::NoSuchBuild(id))
+}
+
+fn print_commits(old: &Entry, new: &Entry) -> Result<()> {
+    let (Some(from), Some(to)) = (&old.commit, &new.commit) else {
+        println!("commits: unknown for build(s) without a package");
+        return Ok(());
+    };
+    if from == to {
+        println!("commits: both builds are of {from}");
+        return Ok(());
+    }
+    // both builds may come from different forks, the newer one has to
+    // contain the older commit for the range to make sense
+    let url = new.source.split('#').next().unwrap_or_default();
+    let commits = git::commit_range(url, from, to)?;
+    println!("commits: {} in {from}..{to}", commits.len());
+    for (oid, summary) in commits {
+        println!(
+            "{}",
+            term::fit(format!("  {:.10} {summary}", oid.to_string()))
+        );
+    }
+    Ok(())
+}
+
+fn print_pkgbuild_diff(old: &Entry, new: &Entry) -> Result<()> {
+    let (Some(a), Some(b)) = (&old.pkgbuild, &new.pkgbuild) else {
+        println!("PKGBUILD: not recorded for build(s) {}/{}", old.id, new.id);
+        return Ok(());
+    };
+    if a == b {
+        println!("PKGBUILD: unchanged");
+        return Ok(());
+    }
+    let mut a_file = tempfile::NamedTempFile::new()?;
+    a_file.write_all(a.as_bytes())?;
+    let mut b_file = tempfile::NamedTempFile::new()?;
+    b_file.write_all(b.as_bytes())?;
+    let diff = Exec::cmd("diff")
+        .arg("-u")
+        .args(&["--label", &format!("PKGBUILD ({})", old.id)])
+        .args(&["--label", &format!("PKGBUILD ({})", new.id)])
+        .arg(a_file.path())
+        .arg(b_file.path())
+        .stdout(Redirection::Pipe)
+        .capture()?;
+    println!("PKGBUILD:");
+    print!("{}", diff.stdout_str());
+    Ok(())
+}
+
+fn package_files(entry: &Entry) -> Result<Option<BTreeSet<String>>> {
+    match entry.package.as_deref().filter(|package| package.exists()) {
+        Some(package) => Ok(Some(files::package_files(package)?)),
+        None => Ok(None),
+    }
+}
+
+fn print_file_diff(old: &Entry, new: &Entry) -> Result<()> {
+    let (Some(a), Some(b)) = (package_files(old)?, package_files(new)?) else {
+        println!(
+            "files: package of build(s) {}/{} is not cached",
+            old.id, new.id
+        );
+        return Ok(());
+    };
+    let added = b.difference(&a).collect::<Vec<_>>();
+    let removed = a.difference(&b).collect::<Vec<_>>();
+    println!("files: {} added, {} removed", added.len(), removed.len());
+    for path in added {
+        println!("  + {path}");
+    }
+    for path in removed {
+        println!("  - {path}");
+    }
+    Ok(())
+}
+
+pub fn diff_builds(args: &DiffBuildsArgs, cache_home: &Path) -> Result<()> {
+    let history = History::load(&history::history_path(cache_home))?;
+    let old = find(&history, args.old)?;
+    let new = find(&history, args.new)?;
+    print_commits(old, new)?;
+    print_pkgbuild_diff(old, new)?;
+    print_file_diff(old, new)
+}
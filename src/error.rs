@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, UpdateError>;
+
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error("AUR URL {url} is unreachable, error: {message}")]
+    CloneFailed { url: String, message: String },
+    #[error("could not patch PKGBUILD: {0}")]
+    PkgbuildPatchFailed(std::io::Error),
+    #[error("PKGBUILD has no {anchors} line(s) to patch, the AUR package changed its layout (pass --allow-unpatched to build anyway)")]
+    PkgbuildAnchorsMissing { anchors: String },
+    #[error("namcap found {errors} error(s) in {}", path.display())]
+    LintFailed { path: PathBuf, errors: usize },
+    #[error("`{0}` is not installed")]
+    MissingTool(String),
+    #[error("Qtile build failed, check in {}", log_path.display())]
+    BuildFailed { log_path: PathBuf },
+    #[error("the signature of the tag could not be verified, check in {} (import the signing key or pass --no-verify)", log_path.display())]
+    SignatureCheckFailed { log_path: PathBuf },
+    #[error("no built package found for {}", dir.display())]
+    PackageNotFound { dir: PathBuf },
+    #[error("Qtile install failed, check in {}", log_path.display())]
+    InstallFailed { log_path: PathBuf },
+    #[error("{} was modified since it was built (sha256 {actual}, expected {expected}), rebuild it", path.display())]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("qtile-git was built for python {package} but python is {interpreter}, rebuild it before restarting")]
+    PythonMismatch {
+        package: String,
+        interpreter: String,
+    },
+    #[error("restart failed, please restart manually: {0}")]
+    RestartFailed(String),
+    #[error("qtile IPC call failed: {0}")]
+    Ipc(String),
+    #[error("could not remove {}", path.display())]
+    RemoveFailed { path: PathBuf },
+    #[error("none of sudo, doas, run0 or pkexec was found, pass --escalate")]
+    NoEscalation,
+    #[error("makepkg can't run as root, pass --build-user")]
+    NoBuildUser,
+    #[error("makepkg runs as `{user}`, who can't write to {}", path.display())]
+    NotWritableByBuildUser { user: String, path: PathBuf },
+    #[error("`{command}` was interrupted")]
+    Interrupted { command: String },
+    #[error("`{command}` timed out after {seconds}s and was stopped")]
+    TimedOut { command: String, seconds: u64 },
+    #[error("there is no failed run to resume")]
+    NothingToResume,
+    #[error("another update is already running (pid {pid})")]
+    AlreadyRunning { pid: String },
+    #[error("stage `{first}` comes after stage `{last}`")]
+    InvalidStageRange { first: String, last: String },
+    #[error("could not fetch {url}: {message}")]
+    FetchFailed { url: String, message: String },
+    #[error("there is no install.log, nothing was built yet")]
+    NoLog,
+    #[error("invalid --commit `{spec}`: {reason}")]
+    InvalidCommits { spec: String, reason: String },
+    #[error("there is no build {0} in the history")]
+    NoSuchBuild(u64),
+    #[error("invalid config {}: {message}", path.display())]
+    InvalidConfig { path: PathBuf, message: String },
+    #[error("there is no profile `{name}` in {}", path.display())]
+    UnknownProfile { name: String, path: PathBuf },
+    #[error("benchmark failed: {0}")]
+    Benchmark(String),
+    #[error("{step} failed on {host}")]
+    DeployFailed { host: String, step: String },
+    #[error("there is no branch `{branch}` in {url}")]
+    NoSuchBranch { url: String, branch: String },
+    #[error("the pacman database is locked by another package manager, remove {} if none is running (see --wait-for-pacman)", path.display())]
+    PacmanLocked { path: PathBuf },
+    #[error("no checksum was recorded for {}, pass --allow-unverified to install it anyway", path.display())]
+    Unverified { path: PathBuf },
+    #[error("qtile-git failed verification: {0}")]
+    Unhealthy(String),
+    #[error("only {free} free in {} but a build needs about {required}, point BUILDDIR to a bigger filesystem or pass --low-space relocate to build elsewhere", dir.display())]
+    NoBuildSpace {
+        dir: PathBuf,
+        free: String,
+        required: String,
+    },
+    #[error("{0} needs a terminal")]
+    NoTerminal(String),
+    #[error("self-update failed: {0}")]
+    SelfUpdateFailed(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Process(#[from] subprocess::PopenError),
+    #[error(transparent)]
+    Prompt(#[from] dialoguer::Error),
+}
+
+impl UpdateError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UpdateError::Interrupted { .. } => 130,
+            // EX_TEMPFAIL, trying again later may work
+            UpdateError::PacmanLocked { .. } => 75,
+            _ => 1,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use subprocess::Exec;
+
+/// How commands that need root are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Escalate {
+    Sudo,
+    Doas,
+    Run0,
+    Pkexec,
+}
+
+impl Escalate {
+    const ALL: [Escalate; 4] = [
+        Escalate::Sudo,
+        Escalate::Doas,
+        Escalate::Run0,
+        Escalate::Pkexec,
+    ];
+
+    pub fn program(self) -> &'static str {
+        match self {
+            Escalate::Sudo => "sudo",
+            Escalate::Doas => "doas",
+            Escalate::Run0 => "run0",
+            Escalate::Pkexec => "pkexec",
+        }
+    }
+
+    /// The first backend found in `PATH`.
+    pub fn detect() -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|backend| in_path(backend.program()))
+    }
+
+    /// `program` wrapped in the escalation command.
+    pub fn cmd(self, program: &str) -> Exec {
+        Exec::cmd(self.program()).arg(program)
+    }
+}
+
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+pub fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use crate::error::Result;
+
+/// Files of the installed `package`, or `None` if it isn't installed.
+pub fn installed_files(package: &str) -> Result<Option<BTreeSet<String>>> {
+    let capture = Exec::cmd("pacman")
+        .args(&["-Qlq", package])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    if !capture.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        capture.stdout_str().lines().map(str::to_owned).collect(),
+    ))
+}
+
+/// What pacman finds wrong with the installed files of `package` when it
+/// compares them to the package's mtree, as (path, problem) pairs.
+pub fn check_installed(package: &str) -> Result<Vec<(String, String)>> {
+    let capture = Exec::cmd("pacman")
+        .args(&["-Qkk", package])
+        // the messages are parsed
+        .env("LC_ALL", "C")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()?;
+    let prefix = format!("warning: {package}: ");
+    Ok(capture
+        .stdout_str()
+        .lines()
+        // `warning: qtile-git: /usr/share/xsessions/qtile.desktop (SHA256 checksum mismatch)`
+        .filter_map(|line| {
+            let (path, problem) = line.strip_prefix(&prefix)?.rsplit_once(" (")?;
+            Some((path.to_owned(), problem.trim_end_matches(')').to_owned()))
+        })
+        .collect())
+}
+
+/// Whether a problem reported by [`check_installed`] means the content of the
+/// file changed, rather than e.g. its modification time.
+pub fn is_modification(problem: &str) -> bool {
+    problem.contains("checksum mismatch") || problem.contains("Size mismatch")
+}
+
+/// Files in a package archive, as absolute paths like pacman lists them.
+pub fn package_files(package: &Path) -> Result<BTreeSet<String>> {
+    let capture = Exec::cmd("bsdtar")
+        .arg("-tf")
+        .arg(package)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    Ok(capture
+        .stdout_str()
+        .lines()
+        // .PKGINFO, .MTREE and friends
+        .filter(|path| !path.starts_with('.'))
+        .map(|path| format!("/{path}"))
+        .collect())
+}
+
+/// Files that are worth a closer look when they appear or disappear.
+fn is_notable(path: &str) -> bool {
+    path.contains("/libqtile/backend/") || path.ends_with(".desktop")
+}
+
+/// Log the files `package` adds and removes compared to what is installed.
+pub fn log_diff(installed: &BTreeSet<String>, package: &BTreeSet<String>) {
+    let relevant = |path: &&String| !path.contains("__pycache__");
+    let added = package
+        .difference(installed)
+        .filter(relevant)
+        .collect::<Vec<_>>();
+    let removed = installed
+        .difference(package)
+        .filter(relevant)
+        .collect::<Vec<_>>();
+    if added.is_empty() && removed.is_empty() {
+        log::info!("the new package contains the same files as the installed one");
+        return;
+    }
+    log::info!(
+        "the new package adds {} and removes {} file(s)",
+        added.len(),
+        removed.len()
+    );
+    for (sign, paths) in [("+", added), ("-", removed)] {
+        for path in paths {
+            if is_notable(path) {
+                log::warn!("  {sign} {path}");
+            } else {
+                log::info!("  {sign} {path}");
+            }
+        }
+    }
+}
+
+/// The paths among `files` that exist on disk but belong to no package.
+pub fn unowned(files: &BTreeSet<String>) -> Result<Vec<String>> {
+    let existing = files
+        .iter()
+        .filter(|path| {
+            Path::new(path)
+                .symlink_metadata()
+                .is_ok_and(|metadata| !metadata.is_dir())
+        })
+        .collect::<Vec<_>>();
+    if existing.is_empty() {
+        return Ok(vec![]);
+    }
+    let capture = Exec::cmd("pacman")
+        .arg("-Qo")
+        .args(&existing)
+        // the messages are parsed
+        .env("LC_ALL", "C")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    Ok(capture
+        .stderr_str()
+        .lines()
+        .filter_map(|line| line.strip_prefix("error: No package owns "))
+        .map(str::to_owned)
+        .collect())
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use git2::{FetchOptions, Oid, Repository};
+
+use crate::error::Result;
+
+/// The URL of the qtile repo to build from, either a GitHub fork or a local path.
+pub fn remote_url(fork: Option<&str>, path: Option<&str>) -> String {
+    if let Some(p) = path {
+        format!("file://{p}")
+    } else if let Some(f) = fork {
+        format!("https://github.com/{f}/qtile")
+    } else {
+        "https://github.com/qtile/qtile".to_owned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteRef {
+    pub name: String,
+    pub oid: Oid,
+}
+
+impl RemoteRef {
+    pub fn branch(&self) -> Option<&str> {
+        self.name.strip_prefix("refs/heads/")
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.name.strip_prefix("refs/tags/")
+    }
+}
+
+/// Branches and tags of `url`, with annotated tags peeled to their commit.
+pub fn ls_remote(url: &str) -> Result<Vec<RemoteRef>> {
+    let mut remote = git2::Remote::create_detached(url)?;
+    remote.connect(git2::Direction::Fetch)?;
+    let mut refs: Vec<RemoteRef> = vec![];
+    for head in remote.list()? {
+        if let Some(tag) = head.name().strip_suffix("^{}") {
+            if let Some(r) = refs.iter_mut().find(|r| r.name == tag) {
+                r.oid = head.oid();
+            }
+        } else if head.name().starts_with("refs/heads/") || head.name().starts_with("refs/tags/") {
+            refs.push(RemoteRef {
+                name: head.name().to_owned(),
+                oid: head.oid(),
+            });
+        }
+    }
+    Ok(refs)
+}
+
+/// Commit times of `refs`, fetched shallowly into a scratch repo unless `url`
+/// is a local path.
+pub fn commit_times(url: &str, refs: &[RemoteRef]) -> Result<HashMap<Oid, i64>> {
+    let scratch = tempfile::tempdir()?;
+    let repo = match url.strip_prefix("file://") {
+        Some(path) => Repository::open(path)?,
+        None => {
+            let repo = Repository::init_bare(scratch.path())?;
+            let refspecs = refs
+                .iter()
+                .map(|r| format!("+{0}:{0}", r.name))
+                .collect::<Vec<_>>();
+            let mut options = FetchOptions::new();
+            options.depth(1);
+            repo.remote_anonymous(url)?
+                .fetch(&refspecs, Some(&mut options), None)?;
+            repo
+        }
+    };
+    Ok(refs
+        .iter()
+        .filter_map(|r| Some((r.oid, repo.find_commit(r.oid).ok()?.time().seconds())))
+        .collect())
+}
+
+/// The last `count` commits of the default branch of `url`, with their subjects.
+pub fn recent_commits(url: &str, count: usize) -> Result<Vec<(Oid, String)>> {
+    let scratch = tempfile::tempdir()?;
+    let (repo, head) = match url.strip_prefix("file://") {
+        Some(path) => {
+            let repo = Repository::open(path)?;
+            let head = repo.head()?.peel_to_commit()?.id();
+            (repo, head)
+        }
+        None => {
+            let repo = Repository::init_bare(scratch.path())?;
+            let mut options = FetchOptions::new();
+            options.depth(count.try_into().unwrap_or(i32::MAX));
+            repo.remote_anonymous(url)?.fetch(
+                &["+HEAD:refs/update-qtile/head"],
+                Some(&mut options),
+                None,
+            )?;
+            let head = repo.refname_to_id("refs/update-qtile/head")?;
+            (repo, head)
+        }
+    };
+    let mut walk = repo.revwalk()?;
+    walk.push(head)?;
+    walk.take(count)
+        .map(|oid| {
+            let commit = repo.find_commit(oid?)?;
+            Ok((commit.id(), commit.summary().unwrap_or_default().to_owned()))
+        })
+        .collect()
+}
+
+pub fn format_date(seconds: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(seconds)
+        .map(|date| date.date().to_string())
+        .unwrap_or_default()
+}
+
+/// Commits reachable from `to` but not from `from`, newest first. Both may be
+/// abbreviated.
+pub fn commit_range(url: &str, from: &str, to: &str) -> Result<Vec<(Oid, String)>> {
+    let scratch = tempfile::tempdir()?;
+    let repo = match url.strip_prefix("file://") {
+        Some(path) => Repository::open(path)?,
+        None => {
+            let repo = Repository::init_bare(scratch.path())?;
+            repo.remote_anonymous(url)?
+                .fetch(&["+refs/heads/*:refs/heads/*"], None, None)?;
+            repo
+        }
+    };
+    let mut walk = repo.revwalk()?;
+    walk.push(repo.revparse_single(to)?.peel_to_commit()?.id())?;
+    walk.hide(repo.revparse_single(from)?.peel_to_commit()?.id())?;
+    walk.map(|oid| {
+        let commit = repo.find_commit(oid?)?;
+        Ok((commit.id(), commit.summary().unwrap_or_default().to_owned()))
+    })
+    .collect()
+}
+
+/// Commit time of what `source` (`url` with an optional `#branch=`, `#tag=`
+/// or `#commit=` fragment) points to. Abbreviated commits of remote repos
+/// can't be fetched on their own, their time is `None`.
+pub fn source_commit_time(source: &str) -> Result<Option<i64>> {
+    let (url, fragment) = source.split_once('#').unwrap_or((source, ""));
+    let target = fragment.split_once('=');
+    let scratch = tempfile::tempdir()?;
+    let (repo, spec) = match url.strip_prefix("file://") {
+        Some(path) => {
+            let spec = target.map_or("HEAD", |(_, value)| value).to_owned();
+            (Repository::open(path)?, spec)
+        }
+        None => {
+            let refspec = match target {
+                Some(("branch", branch)) => format!("refs/heads/{branch}"),
+                Some(("tag", tag)) => format!("refs/tags/{tag}"),
+                Some(("commit", commit)) if commit.len() == 40 => commit.to_owned(),
+                Some(_) => return Ok(None),
+                None => "HEAD".to_owned(),
+            };
+            let repo = Repository::init_bare(scratch.path())?;
+            let mut options = FetchOptions::new();
+            options.depth(1);
+            repo.remote_anonymous(url)?.fetch(
+                &[format!("+{refspec}:refs/update-qtile/source")],
+                Some(&mut options),
+                None,
+            )?;
+            (repo, "refs/update-qtile/source".to_owned())
+        }
+    };
+    let commit = repo.revparse_single(&spec)?.peel_to_commit()?;
+    Ok(Some(commit.time().seconds()))
+}
+
+/// The commit `branch` of `url` currently points to, the default branch when
+/// `branch` is `None`.
+pub fn resolve_branch(url: &str, branch: Option<&str>) -> Result<Option<Oid>> {
+    let mut remote = git2::Remote::create_detached(url)?;
+    remote.connect(git2::Direction::Fetch)?;
+    let name = branch.map_or("HEAD".to_owned(), |branch| format!("refs/heads/{branch}"));
+    Ok(remote
+        .list()?
+        .iter()
+        .find(|head| head.name() == name)
+        .map(|head| head.oid()))
+}
+
+/// Whether the working tree at `path` has uncommitted changes, untracked
+/// files included.
+pub fn is_dirty(path: &str) -> Result<bool> {
+    let repo = Repository::open(path)?;
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let dirty = !repo.statuses(Some(&mut options))?.is_empty();
+    Ok(dirty)
+}
+
+/// Commit the working tree at `path`, as it is, on top of HEAD without
+/// touching the branch or the index, and point `refs/update-qtile/dirty` at
+/// it so that clones of the repo get it.
+pub fn snapshot(path: &str) -> Result<Oid> {
+    let repo = Repository::open(path)?;
+    let head = repo.head()?.peel_to_commit()?;
+    // the index is only changed in memory, it is never written back
+    let mut index = repo.index()?;
+    index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = repo
+        .signature()
+        .or_else(|_| git2::Signature::now("update-qtile", "update-qtile@localhost"))?;
+    let oid = repo.commit(
+        None,
+        &signature,
+        &signature,
+        "update-qtile: snapshot of uncommitted changes",
+        &tree,
+        &[&head],
+    )?;
+    repo.reference(
+        "refs/update-qtile/dirty",
+        oid,
+        true,
+        "update-qtile snapshot",
+    )?;
+    Ok(oid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repo in a temporary directory with `tracked` and `removed`
+    /// committed.
+    fn repo() -> (tempfile::TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("tracked"), "old\n").unwrap();
+        std::fs::write(dir.path().join("removed"), "\n").unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "ignored\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@localhost").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+            .unwrap();
+        drop(tree);
+        (dir, repo)
+    }
+
+    fn path(dir: &tempfile::TempDir) -> &str {
+        dir.path().to_str().unwrap()
+    }
+
+    #[test]
+    fn clean_tree() {
+        let (dir, _repo) = repo();
+        assert!(!is_dirty(path(&dir)).unwrap());
+        std::fs::write(dir.path().join("ignored"), "\n").unwrap();
+        assert!(!is_dirty(path(&dir)).unwrap());
+    }
+
+    #[test]
+    fn dirty_tree() {
+        let (dir, _repo) = repo();
+        std::fs::write(dir.path().join("tracked"), "new\n").unwrap();
+        assert!(is_dirty(path(&dir)).unwrap());
+
+        let (dir, _repo) = repo();
+        std::fs::write(dir.path().join("untracked"), "\n").unwrap();
+        assert!(is_dirty(path(&dir)).unwrap());
+    }
+
+    #[test]
+    fn snapshot_of_working_tree() {
+        let (dir, repo) = repo();
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id();
+        std::fs::write(dir.path().join("tracked"), "new\n").unwrap();
+        std::fs::write(dir.path().join("untracked"), "\n").unwrap();
+        std::fs::write(dir.path().join("ignored"), "\n").unwrap();
+        std::fs::remove_file(dir.path().join("removed")).unwrap();
+
+        let oid = snapshot(path(&dir)).unwrap();
+        let commit = repo.find_commit(oid).unwrap();
+        assert_eq!(commit.parent_ids().collect::<Vec<_>>(), [head]);
+        let tree = commit.tree().unwrap();
+        let blob = tree.get_name("tracked").unwrap().to_object(&repo).unwrap();
+        assert_eq!(blob.as_blob().unwrap().content(), b"new\n");
+        assert!(tree.get_name("untracked").is_some());
+        assert!(tree.get_name("ignored").is_none());
+        assert!(tree.get_name("removed").is_none());
+        assert_eq!(repo.refname_to_id("refs/update-qtile/dirty").unwrap(), oid);
+
+        // neither the branch nor the index moved
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), head);
+        let statuses = repo.statuses(None).unwrap();
+        assert!(statuses.iter().all(|entry| !entry.status().intersects(
+            git2::Status::INDEX_NEW | git2::Status::INDEX_MODIFIED | git2::Status::INDEX_DELETED
+        )));
+        assert!(is_dirty(path(&dir)).unwrap());
+    }
+}
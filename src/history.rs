@@ -0,0 +1,300 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, UpdateError};
+use crate::stage::Stage;
+use crate::term;
+
+/// How many of the latest successful runs the build time estimate is based on.
+const ESTIMATE_RUNS: usize = 5;
+
+pub fn history_path(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("history.json")
+}
+
+/// Built packages are copied here so that they outlive the next clone.
+pub fn packages_dir(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("packages")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Success,
+    Failed,
+    Interrupted,
+}
+
+/// One invocation of `update-qtile update`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    /// Unix timestamp of the start of the run.
+    pub started: i64,
+    pub source: String,
+    pub commit: Option<String>,
+    /// Seconds spent in every stage that ran.
+    pub stages: Vec<(Stage, u64)>,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    pub package: Option<PathBuf>,
+    /// Checksum of the package right after it was built.
+    pub sha256: Option<String>,
+    /// The patched PKGBUILD the package was built from.
+    pub pkgbuild: Option<String>,
+    /// Tracebacks qtile logged right after being restarted.
+    pub crash_log: Option<String>,
+}
+
+impl Entry {
+    pub fn new(id: u64, source: String) -> Self {
+        let commit = source
+            .split_once("#commit=")
+            .map(|(_, commit)| commit.to_owned());
+        Self {
+            id,
+            started: time::OffsetDateTime::now_utc().unix_timestamp(),
+            source,
+            commit,
+            stages: Vec::new(),
+            outcome: Outcome::Success,
+            error: None,
+            package: None,
+            sha256: None,
+            pkgbuild: None,
+            crash_log: None,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.stages.iter().map(|(_, seconds)| seconds).sum()
+    }
+
+    pub fn finish(&mut self, result: &Result<()>) {
+        self.outcome = match result {
+            Ok(()) => Outcome::Success,
+            Err(UpdateError::Interrupted { .. }) => Outcome::Interrupted,
+            Err(_) => Outcome::Failed,
+        };
+        self.error = result.as_ref().err().map(ToString::to_string);
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+impl History {
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn next_id(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1
+    }
+
+    /// Average time `stage` took in the latest successful runs.
+    pub fn estimate(&self, stage: Stage) -> Option<Duration> {
+        let durations: Vec<u64> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.outcome == Outcome::Success)
+            .filter_map(|entry| entry.stages.iter().find(|(s, _)| *s == stage))
+            .map(|(_, seconds)| *seconds)
+            .take(ESTIMATE_RUNS)
+            .collect();
+        let count = u64::try_from(durations.len()).ok().filter(|n| *n > 0)?;
+        Some(Duration::from_secs(durations.iter().sum::<u64>() / count))
+    }
+}
+
+/// The abbreviated commit in a -git package name, e.g.
+/// `qtile-git-0.29.0.r12.gabc1234-1-x86_64.pkg.tar.zst`.
+pub fn commit_from_package(package: &Path) -> Option<String> {
+    let name = package.file_name()?.to_str()?;
+    let commit = Regex::new(r"\.g([0-9a-f]{7,})-").unwrap();
+    Some(commit.captures(name)?[1].to_owned())
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn format_time(seconds: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(seconds)
+        .map(|time| format!("{} {:02}:{:02}", time.date(), time.hour(), time.minute()))
+        .unwrap_or_default()
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HistoryArgs {
+    /// Only show the latest N runs
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+    /// Only show runs with this outcome
+    #[arg(long)]
+    outcome: Option<Outcome>,
+    /// Only show runs whose source or commit contains this
+    #[arg(long)]
+    source: Option<String>,
+    /// Show the time spent in every stage
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+}
+
+pub fn history(args: &HistoryArgs, cache_home: &Path) -> Result<()> {
+    let history = History::load(&history_path(cache_home))?;
+    let entries: Vec<&Entry> = history
+        .entries
+        .iter()
+        .rev()
+        .filter(|entry| args.outcome.is_none_or(|outcome| entry.outcome == outcome))
+        .filter(|entry| {
+            args.source.as_deref().is_none_or(|source| {
+                entry.source.contains(source)
+                    || entry.commit.as_deref().is_some_and(|c| c.contains(source))
+            })
+        })
+        .take(args.limit)
+        .collect();
+    if entries.is_empty() {
+        log::info!("no runs recorded");
+        return Ok(());
+    }
+    for entry in entries.into_iter().rev() {
+        let outcome = entry
+            .outcome
+            .to_possible_value()
+            .expect("no outcome is skipped");
+        println!(
+            "{}",
+            term::fit(format!(
+                "{:>4} {} {:<11} {:>8} {:<8} {}",
+                entry.id,
+                format_time(entry.started),
+                outcome.get_name(),
+                format_duration(entry.total()),
+                entry.commit.as_deref().unwrap_or("-"),
+                entry.source
+            ))
+        );
+        if args.verbose {
+            for (stage, seconds) in &entry.stages {
+                println!("       {:<10} {}", stage.name(), format_duration(*seconds));
+            }
+            if let Some(error) = &entry.error {
+                println!("       error: {error}");
+            }
+            if entry.crash_log.is_some() {
+                println!("       qtile logged errors after restarting");
+            }
+            if let Some(package) = &entry.package {
+                println!("       package: {}", package.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, outcome: Outcome, stages: &[(Stage, u64)]) -> Entry {
+        let mut entry = Entry::new(id, "https://github.com/qtile/qtile.git".to_owned());
+        entry.outcome = outcome;
+        entry.stages = stages.to_vec();
+        entry
+    }
+
+    #[test]
+    fn no_estimate_without_successful_runs() {
+        let history = History {
+            entries: vec![entry(1, Outcome::Failed, &[(Stage::Build, 100)])],
+        };
+        assert_eq!(history.estimate(Stage::Build), None);
+        assert_eq!(History::default().estimate(Stage::Build), None);
+    }
+
+    #[test]
+    fn estimate_averages_latest_successful_runs() {
+        let mut entries = vec![
+            // older than the latest ESTIMATE_RUNS successful runs
+            entry(1, Outcome::Success, &[(Stage::Build, 1000)]),
+        ];
+        for id in 2..=6 {
+            entries.push(entry(id, Outcome::Success, &[(Stage::Build, id * 10)]));
+        }
+        entries.push(entry(7, Outcome::Failed, &[(Stage::Build, 5000)]));
+        // runs that skipped the stage don't count
+        entries.push(entry(8, Outcome::Success, &[(Stage::Install, 5)]));
+        let history = History { entries };
+        assert_eq!(
+            history.estimate(Stage::Build),
+            Some(Duration::from_secs((20 + 30 + 40 + 50 + 60) / 5))
+        );
+        assert_eq!(
+            history.estimate(Stage::Install),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(history.estimate(Stage::Clone), None);
+    }
+
+    #[test]
+    fn commit_of_package() {
+        assert_eq!(
+            commit_from_package(Path::new(
+                "/cache/qtile-git-0.29.0.r12.gabc1234-1-x86_64.pkg.tar.zst"
+            ))
+            .as_deref(),
+            Some("abc1234")
+        );
+        assert_eq!(
+            commit_from_package(Path::new(
+                "qtile-git-0.29.0.r0.g0123456789ab-2-any.pkg.tar.xz"
+            ))
+            .as_deref(),
+            Some("0123456789ab")
+        );
+        assert_eq!(
+            commit_from_package(Path::new("qtile-git-0.29.0-1-x86_64.pkg.tar.zst")),
+            None
+        );
+    }
+
+    #[test]
+    fn commit_from_source() {
+        let entry = Entry::new(
+            1,
+            "https://github.com/qtile/qtile.git#commit=abc1234".to_owned(),
+        );
+        assert_eq!(entry.commit.as_deref(), Some("abc1234"));
+        let entry = Entry::new(
+            2,
+            "https://github.com/qtile/qtile.git#tag=v0.29.0".to_owned(),
+        );
+        assert_eq!(entry.commit, None);
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use subprocess::{Exec, Redirection};
+
+use crate::error::{Result, UpdateError};
+use crate::{ipc, process};
+
+/// How long without input counts as idle.
+const IDLE_AFTER: Duration = Duration::from_secs(2 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+fn fullscreen_windows() -> Result<Vec<String>> {
+    let windows = ipc::call(&[], "windows", &[])?;
+    Ok(windows
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|window| window["fullscreen"].as_bool().unwrap_or(false))
+        .map(|window| window["name"].as_str().unwrap_or_default().to_owned())
+        .collect())
+}
+
+/// Time since the last input, when it can be told (X11 with xprintidle).
+fn input_idle() -> Option<Duration> {
+    std::env::var_os("DISPLAY")?;
+    let capture = Exec::cmd("xprintidle")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .ok()?;
+    let millis = capture.stdout_str().trim().parse().ok()?;
+    Some(Duration::from_millis(millis))
+}
+
+/// Wait until no window is fullscreen and there was no recent input, or
+/// until `deadline` has passed.
+pub fn wait_until_idle(deadline: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        let fullscreen = fullscreen_windows()?;
+        let idle = input_idle();
+        if fullscreen.is_empty() && idle.is_none_or(|idle| idle >= IDLE_AFTER) {
+            return Ok(());
+        }
+        if start.elapsed() >= deadline {
+            log::warn!("session is still busy, restarting anyway");
+            return Ok(());
+        }
+        if !fullscreen.is_empty() {
+            log::info!("postponing restart, fullscreen: {}", fullscreen.join(", "));
+        } else {
+            log::info!("postponing restart, the session is in use");
+        }
+        let slept = Instant::now();
+        while slept.elapsed() < POLL_INTERVAL {
+            if process::interrupted() {
+                return Err(UpdateError::Interrupted {
+                    command: "restart".to_owned(),
+                });
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use qtile_client_lib::utils::client::InteractiveCommandClient;
+use serde_json::Value;
+use subprocess::{Exec, Redirection};
+
+use crate::error::{Result, UpdateError};
+use crate::escalate;
+use crate::xdg;
+
+/// Call `function` on the qtile command object at `object`, e.g.
+/// `call(&["window", "123"], "togroup", &["2"])`.
+pub fn call(object: &[&str], function: &str, args: &[&str]) -> Result<Value> {
+    InteractiveCommandClient::call(
+        Some(object.iter().map(|s| s.to_string()).collect()),
+        Some(function.to_owned()),
+        Some(args.iter().map(|s| s.to_string()).collect()),
+        false,
+    )
+    .map_err(|err| UpdateError::Ipc(err.to_string()))
+}
+
+const RESTART_ATTEMPTS: u32 = 3;
+/// How long qtile gets to come back after restarting.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long qtile gets to stop answering once it accepted a restart.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether a failed call means qtile went away while answering, which is
+/// what a restart looks like from this side.
+fn is_disconnect(err: &UpdateError) -> bool {
+    let message = err.to_string().to_lowercase();
+    ["reset", "broken pipe", "eof", "end of file", "closed"]
+        .iter()
+        .any(|symptom| message.contains(symptom))
+}
+
+/// Identifies the socket file, which qtile recreates when it restarts.
+fn socket_id() -> Option<(u64, u64)> {
+    let metadata = socket_path()?.metadata().ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Wait for the instance that was listening on `socket` to go away. qtile
+/// answers a restart before it re-executes, so until then it still answers.
+fn wait_until_stopped(socket: Option<(u64, u64)>) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        if socket_id() != socket || call(&[], "status", &[]).is_err() {
+            return Ok(());
+        }
+        if start.elapsed() >= STOP_TIMEOUT {
+            return Err(UpdateError::RestartFailed(
+                "qtile accepted the restart but kept running".to_owned(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn wait_after_restart(socket: Option<(u64, u64)>) -> Result<()> {
+    wait_until_stopped(socket)?;
+    wait_until_ready(READY_TIMEOUT).map_err(|err| {
+        UpdateError::RestartFailed(format!("qtile didn't come back after restarting: {err}"))
+    })
+}
+
+/// Restart qtile, retrying with a backoff and falling back to the `qtile`
+/// command, and wait until it answers again.
+pub fn restart() -> Result<()> {
+    let socket = socket_id();
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=RESTART_ATTEMPTS {
+        match call(&[], "restart", &[]) {
+            Ok(Value::Null) => return wait_after_restart(socket),
+            // only errors are fatal, anything else qtile says is informational
+            Ok(value) => {
+                log::info!("qtile answered the restart with {value}");
+                return wait_after_restart(socket);
+            }
+            Err(err) if is_disconnect(&err) => return wait_after_restart(socket),
+            Err(err) => {
+                log::warn!("restart attempt {attempt}/{RESTART_ATTEMPTS} failed: {err}");
+                if attempt < RESTART_ATTEMPTS {
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+            }
+        }
+    }
+    log::info!("trying `qtile cmd-obj -o cmd -f restart`");
+    let capture = Exec::cmd("qtile")
+        .args(&["cmd-obj", "-o", "cmd", "-f", "restart"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture();
+    match capture {
+        Ok(capture) if capture.success() => wait_after_restart(socket),
+        Ok(capture) => Err(UpdateError::RestartFailed(
+            capture.stdout_str().trim().to_owned() + "\nQtile is probably not running",
+        )),
+        Err(err) => Err(UpdateError::RestartFailed(
+            err.to_string() + "\nQtile is probably not running",
+        )),
+    }
+}
+
+/// Wait for qtile to answer again, e.g. after a restart.
+pub fn wait_until_ready(timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        match call(&[], "status", &[]) {
+            Ok(_) => return Ok(()),
+            Err(err) if start.elapsed() >= timeout => return Err(err),
+            Err(_) => std::thread::sleep(Duration::from_millis(500)),
+        }
+    }
+}
+
+/// Where qtile puts its socket for the current display.
+fn socket_path() -> Option<PathBuf> {
+    let display = std::env::var("WAYLAND_DISPLAY")
+        .or_else(|_| std::env::var("DISPLAY"))
+        .ok()?;
+    Some(
+        xdg::cache_home()
+            .join("qtile")
+            .join(format!("qtilesocket.{display}")),
+    )
+}
+
+/// Check that a restart would get through: the socket is there and a no-op
+/// command round-trips.
+pub fn check_restart() -> Result<()> {
+    if escalate::is_root() {
+        log::warn!("running as root, the qtile of your user may not be reachable");
+    }
+    match socket_path() {
+        Some(path) if path.exists() => log::info!("found qtile socket {:?}", path),
+        Some(path) => log::warn!("there is no qtile socket at {:?}", path),
+        None => log::warn!("neither WAYLAND_DISPLAY nor DISPLAY is set"),
+    }
+    match call(&[], "status", &[])? {
+        Value::String(status) if status == "OK" => {
+            log::info!("qtile answered, restarting will work");
+            Ok(())
+        }
+        other => Err(UpdateError::Ipc(format!("unexpected status {other}"))),
+    }
+}
@@ -0,0 +1,36 @@
+use std::path::Path;
+
+use subprocess::{Exec, PopenError, Redirection};
+
+use crate::error::{Result, UpdateError};
+
+/// Run namcap on a PKGBUILD or package and log what it reports.
+///
+/// Returns the number of errors found.
+pub fn namcap(target: &Path) -> Result<usize> {
+    log::info!("running namcap on {:?}", target);
+    let capture = match Exec::cmd("namcap")
+        .arg(target)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+    {
+        Ok(capture) => capture,
+        Err(PopenError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(UpdateError::MissingTool("namcap".to_owned()));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut errors = 0;
+    for line in capture.stdout_str().lines() {
+        if line.contains(" E: ") {
+            errors += 1;
+            log::error!("namcap: {line}");
+        } else if line.contains(" W: ") {
+            log::warn!("namcap: {line}");
+        } else {
+            log::info!("namcap: {line}");
+        }
+    }
+    Ok(errors)
+}
@@ -1,16 +1,104 @@
+mod batch;
+mod bench;
+mod build_log;
+mod build_space;
+mod cache;
+mod check;
+mod clone;
+mod config;
+mod conflicts;
+mod crashlog;
+mod db_lock;
+mod deploy;
+mod deps;
+mod diff_builds;
+mod error;
+mod escalate;
+mod files;
+mod git;
+mod history;
+mod idle;
+mod ipc;
+mod lint;
+mod pick;
+mod pkgbuild;
+mod process;
+mod python;
+mod refs;
+mod self_update;
+mod session;
+mod sessions;
+mod stage;
+mod status;
+mod term;
+mod verify;
+mod xdg;
+
 use std::io::Write;
-use std::{fs::OpenOptions, path::Path, process::exit};
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    process::exit,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
-use qtile_client_lib::utils::client::InteractiveCommandClient;
-use regex::Regex;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use error::{Result, UpdateError};
+use escalate::Escalate;
+use history::History;
+use serde::Serialize;
+use stage::{RunLock, RunState, Stage};
 use subprocess::{Exec, Redirection};
-use text_io::read;
 
 /// Qtile command client
 #[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
-pub struct Args {
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    update: UpdateArgs,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Build and install qtile-git (the default when no subcommand is given)
+    Update(Box<UpdateArgs>),
+    /// Remove build trees left by --keep-build or failed builds
+    Clean {
+        /// How to run commands that need root, detected from PATH by default
+        #[arg(long, value_enum)]
+        escalate: Option<Escalate>,
+    },
+    /// List the branches and tags of the selected repo
+    Refs(refs::RefsArgs),
+    /// Check whether new commits or releases are available, without building
+    Check(check::CheckArgs),
+    /// Show the installed and running qtile versions
+    Status,
+    /// Show the log of the current or last run
+    Log(build_log::LogArgs),
+    /// Check the installed qtile-git for modified or missing files
+    Verify,
+    /// List past runs
+    History(history::HistoryArgs),
+    /// Compare two builds from the history
+    DiffBuilds(diff_builds::DiffBuildsArgs),
+    /// Show or edit the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show or prune the cached packages
+    Cache(cache::CacheArgs),
+    /// Install a build from the history on other machines over SSH
+    Deploy(deploy::DeployArgs),
+    /// Update update-qtile itself
+    SelfUpdate(self_update::SelfUpdateArgs),
+}
+
+#[derive(clap::Args, Debug, Clone, Default, Serialize)]
+pub struct UpdateArgs {
     #[arg(
         short,
         long,
@@ -22,339 +110,1358 @@ pub struct Args {
     fork: Option<String>,
     #[arg(short, long, num_args = 1, default_value = None, group = "remote")]
     path: Option<String>,
+    /// Commit to build, or `A..B` or `A,B,C` to build several in turn
     #[arg(short, long, num_args = 1, default_value = None, group = "identifier",conflicts_with_all = ["branch", "tag"])]
     commit: Option<String>,
-    #[arg(short, long, num_args = 1, default_value = None, group = "identifier")]
+    /// Only build every Nth commit of a `--commit A..B` range
+    #[arg(long, value_name = "N", requires = "commit", value_parser = clap::value_parser!(u64).range(1..))]
+    step: Option<u64>,
+    /// Command testing each commit of a batch, instead of asking (exit status 0
+    /// means good)
+    #[arg(long, value_name = "COMMAND", requires = "commit")]
+    test_cmd: Option<String>,
+    /// Branch to build, pick one interactively if no value is given
+    #[arg(short, long, num_args = 0..=1, default_value = None, default_missing_value = "", group = "identifier")]
     branch: Option<String>,
     #[arg(short, long, num_args = 1, default_value = None, group = "identifier")]
     tag: Option<String>,
+    /// Build the uncommitted changes of --path too, from a snapshot commit
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "path",
+        conflicts_with = "identifier"
+    )]
+    include_dirty: bool,
     #[arg(short, long, default_value_t = false)]
     restart: bool,
-}
-
-fn error_and_exit(err: &str) {
-    log::error!("{err}");
-    exit(1);
+    /// Postpone the restart until nothing is fullscreen and the session is idle,
+    /// for at most this many minutes
+    #[arg(long, value_name = "MINUTES", num_args = 0..=1, default_missing_value = "60", requires = "restart")]
+    restart_when_idle: Option<u64>,
+    /// Don't restart, pick up the new version at the next login instead
+    #[arg(long, default_value_t = false, conflicts_with = "restart")]
+    restart_on_login: bool,
+    /// Only check that qtile can be reached to restart it, don't update
+    #[arg(long, default_value_t = false)]
+    check_restart: bool,
+    /// Put windows back on their groups and groups on their screens after restarting
+    #[arg(long, default_value_t = false, requires = "restart")]
+    preserve_layout: bool,
+    /// Pick the branch, tag or commit to build from a searchable list
+    #[arg(short, long, default_value_t = false, conflicts_with_all = ["commit", "tag", "resume"])]
+    interactive: bool,
+    /// Continue a failed run after its last successful stage
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+    /// Start at this stage instead of the beginning
+    #[arg(long, value_enum, conflicts_with = "resume")]
+    from_stage: Option<Stage>,
+    /// Stop after this stage
+    #[arg(long, value_enum)]
+    until_stage: Option<Stage>,
+    /// How to run commands that need root, detected from PATH by default
+    #[arg(long, value_enum)]
+    escalate: Option<Escalate>,
+    /// User to run makepkg as when running as root, defaults to $SUDO_USER
+    #[arg(long, value_name = "USER")]
+    build_user: Option<String>,
+    /// Don't clean up the build tree after building
+    #[arg(long, default_value_t = false)]
+    keep_build: bool,
+    /// Don't list the files added and removed by the new package
+    #[arg(long, default_value_t = false)]
+    no_file_diff: bool,
+    /// Check the patched PKGBUILD and the built package with namcap
+    #[arg(long, default_value_t = false)]
+    lint: bool,
+    /// Build against this wlroots version, e.g. `0.17`
+    #[arg(long, value_name = "VERSION", value_parser = pkgbuild::parse_wlroots_version)]
+    wlroots: Option<String>,
+    /// Tags pkgver is derived from: `upstream` (qtile's, the default), `fork`
+    /// (the built repo's own) or the URL of another repo
+    #[arg(long, value_name = "SOURCE", value_parser = pkgbuild::parse_version_source)]
+    version_source: Option<String>,
+    /// Add a package to the PKGBUILD's depends, can be repeated
+    #[arg(long = "add-depend", value_name = "PACKAGE")]
+    add_depends: Vec<String>,
+    /// Add a package to the PKGBUILD's makedepends, can be repeated
+    #[arg(long = "add-makedepend", value_name = "PACKAGE")]
+    add_makedepends: Vec<String>,
+    /// Only handle the session files of this backend
+    #[arg(long, value_enum)]
+    backend: Option<sessions::Backend>,
+    /// Compare qtile's startup time before and after installing
+    #[arg(long, default_value_t = false)]
+    benchmark: bool,
+    /// Config to benchmark with instead of qtile's default one
+    #[arg(long, value_name = "PATH", requires = "benchmark")]
+    benchmark_config: Option<PathBuf>,
+    /// Don't verify the signature of --tag builds
+    #[arg(long, default_value_t = false)]
+    no_verify: bool,
+    /// Install the package even when no checksum was recorded for it
+    #[arg(long, default_value_t = false)]
+    allow_unverified: bool,
+    /// Only accept tag signatures by this key fingerprint (repeatable)
+    #[arg(long = "valid-key", value_name = "FINGERPRINT")]
+    valid_keys: Vec<String>,
+    /// GnuPG home with the keys to verify signatures against
+    #[arg(long, env = "GNUPGHOME", value_name = "DIR")]
+    keyring: Option<PathBuf>,
+    /// Packager recorded in the package, e.g. `Name <mail@example.com>`
+    #[arg(long, env = "PACKAGER", value_name = "PACKAGER")]
+    packager: Option<String>,
+    /// Build even if some of the PKGBUILD changes couldn't be made
+    #[arg(long, default_value_t = false)]
+    allow_unpatched: bool,
+    /// makepkg.conf to build with instead of the system one
+    #[arg(long, env = "MAKEPKG_CONF", value_name = "PATH")]
+    makepkg_conf: Option<PathBuf>,
+    /// What to do when BUILDDIR is too small for the build, e.g. a small tmpfs
+    #[arg(long, value_enum, default_value = "relocate")]
+    low_space: build_space::LowSpace,
+    /// Stop a stage that runs longer than this, e.g. `build=30m` (repeatable)
+    #[arg(long = "timeout", value_name = "STAGE=DURATION", value_parser = process::parse_stage_timeout)]
+    #[serde(serialize_with = "config::serialize_timeouts")]
+    timeouts: Vec<(Stage, Duration)>,
+    /// Wait this long for another pacman to finish instead of stopping before
+    /// the install, e.g. `10m`
+    #[arg(long, value_name = "DURATION", value_parser = process::parse_duration)]
+    #[serde(serialize_with = "config::serialize_duration")]
+    wait_for_pacman: Option<Duration>,
+    /// Take the options not given on the command line from this profile of
+    /// the config file
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
 }
 
 struct UpdateQtile {
     repo_path: Box<Path>,
-    args: Args,
+    build_path: Box<Path>,
+    state_path: PathBuf,
+    lock_path: PathBuf,
+    layout_path: PathBuf,
+    pending_login_path: PathBuf,
+    history_path: PathBuf,
+    packages_dir: PathBuf,
+    sessions_backup_dir: PathBuf,
+    /// Builds go here when BUILDDIR is too small.
+    relocated_build_dir: PathBuf,
+    args: UpdateArgs,
 }
 impl UpdateQtile {
-    pub fn new(args: Args) -> Self {
-        let xdg_cache_home = std::env::var("XDG_CACHE_HOME").unwrap_or("~/.cache".to_string());
-        let repo_path = Path::new(&xdg_cache_home)
-            .join("yay")
-            .join("qtile-git")
-            .as_path()
-            .into();
-        Self { repo_path, args }
-    }
-    fn get_source(&self) -> String {
-        let source = if let Some(p) = &self.args.path {
-            format!("file://{p}")
-        } else if let Some(f) = &self.args.fork {
-            format!("https://github.com/{f}/qtile")
-        } else {
-            "https://github.com/qtile/qtile".to_owned()
-        };
+    pub fn new(args: UpdateArgs) -> Self {
+        let cache_home = xdg::cache_home();
+        let repo_path: Box<Path> = cache_home.join("yay").join("qtile-git").as_path().into();
+        // new builds happen next to the cache so that swapping them in is a rename
+        let build_path = repo_path.with_extension("new").as_path().into();
+        Self {
+            repo_path,
+            build_path,
+            state_path: stage::state_path(&cache_home),
+            lock_path: stage::lock_path(&cache_home),
+            layout_path: cache_home.join("update-qtile").join("layout.json"),
+            pending_login_path: status::pending_login_path(&cache_home),
+            history_path: history::history_path(&cache_home),
+            packages_dir: history::packages_dir(&cache_home),
+            sessions_backup_dir: cache_home.join("update-qtile").join("sessions"),
+            relocated_build_dir: cache_home.join("update-qtile").join("build"),
+            args,
+        }
+    }
+    fn get_source(&self) -> Result<String> {
+        let source = git::remote_url(self.args.fork.as_deref(), self.args.path.as_deref());
+        if let Some(path) = &self.args.path {
+            if git::is_dirty(path)? {
+                if self.args.include_dirty {
+                    let oid = git::snapshot(path)?;
+                    log::info!("selected repo `{source}` - snapshot `{oid}` of the working tree");
+                    return Ok(format!("{}#commit={}", source, oid));
+                }
+                log::warn!(
+                    "`{path}` has uncommitted changes that won't be built, pass --include-dirty to build them"
+                );
+            }
+        }
         if let Some(c) = &self.args.commit {
             log::info!("selected repo `{}` - commit `{}`", source, c);
-            format!("{}#commit={}", source, c)
+            Ok(format!("{}#commit={}", source, c))
         } else if let Some(t) = &self.args.tag {
             log::info!("selected repo `{}` - tag `{}`", source, t);
-            format!("{}#tag={}", source, t)
-        } else if let Some(b) = &self.args.branch {
-            log::info!("selected repo `{}` - branch `{}`", source, b);
-            format!("{}#branch={}", source, b)
+            Ok(format!("{}#tag={}", source, t))
         } else {
-            log::info!("selected repo `{}` - branch `master`", source);
-            source
+            let branch = self.args.branch.as_deref();
+            log::info!(
+                "selected repo `{}` - branch `{}`",
+                source,
+                branch.unwrap_or("master")
+            );
+            // pin the branch so that what is built can't change mid-run
+            match git::resolve_branch(&source, branch)? {
+                Some(oid) => {
+                    log::info!("branch is at commit `{oid}`");
+                    Ok(format!("{}#commit={}", source, oid))
+                }
+                None => Err(UpdateError::NoSuchBranch {
+                    url: source,
+                    branch: branch.unwrap_or("HEAD").to_owned(),
+                }),
+            }
         }
     }
-    fn remove_repo(&self) -> anyhow::Result<()> {
-        if self.repo_path.exists() {
-            log::info!("removing cached AUR repo {:?}", self.repo_path);
-            match std::fs::remove_dir_all(&self.repo_path) {
+    fn escalate(&self) -> Result<Escalate> {
+        self.args
+            .escalate
+            .or_else(Escalate::detect)
+            .ok_or(UpdateError::NoEscalation)
+    }
+    /// `program`, escalated unless we already are root.
+    fn privileged(&self, program: &str) -> Result<Exec> {
+        if escalate::is_root() {
+            Ok(Exec::cmd(program))
+        } else {
+            Ok(self.escalate()?.cmd(program))
+        }
+    }
+    fn build_user(&self) -> Result<String> {
+        self.args
+            .build_user
+            .clone()
+            .or_else(|| std::env::var("SUDO_USER").ok())
+            .filter(|user| !user.is_empty() && user != "root")
+            .ok_or(UpdateError::NoBuildUser)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            log::info!("removing {:?}", path);
+            match std::fs::remove_dir_all(path) {
                 Ok(()) => {}
                 Err(err) => {
-                    log::error!("couldn't remove AUR cached repo");
+                    log::error!("couldn't remove {:?}", path);
                     log::error!("\tError: {err}");
-                    log::info!("Would you like to try with root permissions? [Y/n]");
-                    let ans: String = read!("{}\n");
-                    if ["Y", "y", ""].contains(&ans.as_str()) {
-                        let repo_path = self.repo_path.as_os_str();
-                        let exit_status = Exec::shell(format!("sudo rm -rf {repo_path:?}"))
+                    if !term::confirm("Would you like to try with root permissions?")
+                        || !self
+                            .privileged("rm")?
+                            .arg("-rf")
+                            .arg(path)
                             .join()?
-                            .success();
-                        match exit_status {
-                            true => {}
-                            false => error_and_exit("could not run sudo"),
-                        }
+                            .success()
+                    {
+                        return Err(UpdateError::RemoveFailed { path: path.into() });
                     }
                 }
             }
         }
         Ok(())
     }
-    fn clone_repo(&self) -> anyhow::Result<()> {
+    fn remove_stale_build(&self) -> Result<()> {
+        self.remove_dir(&self.build_path)
+    }
+    /// Remove build trees kept by `--keep-build` and failed builds.
+    fn clean(&self) -> Result<()> {
+        let _lock = RunLock::acquire(&self.lock_path)?;
+        self.remove_dir(&self.repo_path.join("src"))?;
+        self.remove_dir(&self.repo_path.join("pkg"))?;
+        // trees kept where BUILDDIR pointed or where the build was relocated
+        let configured =
+            build_space::build_dir(self.args.makepkg_conf.as_deref(), &self.build_path);
+        for dir in [configured, self.relocated_build_dir.clone()] {
+            if dir != *self.build_path {
+                self.remove_dir(&dir.join("qtile-git"))?;
+            }
+        }
+        // staging directories of root builds kept with a relocated build tree,
+        // root runs don't share our lock so their pid is checked too
+        let pattern = Path::new(STAGING_DIR).join(format!("{STAGING_PREFIX}*"));
+        for staging in glob::glob(&pattern.to_string_lossy())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            if let Some(pid) = staging_pid(&staging)
+                .filter(|pid| Path::new("/proc").join(pid.to_string()).exists())
+            {
+                log::info!("skipping {:?}, run {pid} is using it", staging);
+                continue;
+            }
+            self.remove_dir(&staging)?;
+        }
+        self.remove_stale_build()
+    }
+    /// Swap the freshly built repo in place of the cached one.
+    ///
+    /// The old cache is only deleted once the new one has been moved into place.
+    fn replace_cache(&self) -> Result<()> {
+        log::info!("replacing cached AUR repo {:?}", self.repo_path);
+        let old_path = self.repo_path.with_extension("old");
+        self.remove_dir(&old_path)?;
+        if self.repo_path.exists() {
+            std::fs::rename(&self.repo_path, &old_path)?;
+        }
+        std::fs::rename(&self.build_path, &self.repo_path)?;
+        self.remove_dir(&old_path)
+    }
+    fn timeout(&self, stage: Stage) -> Option<Duration> {
+        self.args
+            .timeouts
+            .iter()
+            .rev()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, timeout)| *timeout)
+    }
+    /// How long `stage` usually takes, from the history of past runs.
+    fn estimate(&self, stage: Stage) -> Option<Duration> {
+        History::load(&self.history_path).ok()?.estimate(stage)
+    }
+    fn clone_repo(&self) -> Result<()> {
+        clone::set_timeouts(self.timeout(Stage::Clone))?;
+        self.fetch_aur()
+    }
+    /// The clone itself, without the libgit2 setup of [`Self::clone_repo`].
+    fn fetch_aur(&self) -> Result<()> {
         log::info!("cloning AUR repo");
         let aur_url = "https://aur.archlinux.org/qtile-git";
-        match git2::Repository::clone(aur_url, &self.repo_path) {
-            Ok(_) => self.modify_pkgbuild()?,
-            Err(err) => error_and_exit(
-                ("AUR URL ".to_owned() + aur_url + " is unreachable, error: " + &err.to_string())
-                    .as_str(),
-            ),
+        // left behind by an interrupted clone
+        self.remove_dir(&self.build_path)?;
+        clone::shallow_clone(aur_url, &self.build_path, self.timeout(Stage::Clone))
+    }
+    /// Clean and clone the AUR repo while the upstream branch is resolved,
+    /// both wait on the network and neither needs the other.
+    ///
+    /// Returns the source and the clean and clone timings.
+    fn prefetch(&self) -> Result<(String, Result<StageTimings>)> {
+        clone::set_timeouts(self.timeout(Stage::Clone))?;
+        std::thread::scope(|scope| {
+            let fetch = scope.spawn(|| {
+                let start = Instant::now();
+                self.remove_stale_build()?;
+                let clean = start.elapsed().as_secs();
+                let start = Instant::now();
+                let result = self.fetch_aur();
+                let timings = vec![
+                    (Stage::Clean, clean),
+                    (Stage::Clone, start.elapsed().as_secs()),
+                ];
+                result.map(|()| timings)
+            });
+            let source = self.get_source();
+            let fetched = fetch.join().expect("the AUR clone doesn't panic");
+            Ok((source?, fetched))
+        })
+    }
+
+    fn modify_pkgbuild(&self, source: &str) -> Result<()> {
+        log::info!("modifying PKGBUILD");
+        let lines = std::fs::read_to_string(self.build_path.join("PKGBUILD"))
+            .map_err(UpdateError::PkgbuildPatchFailed)?;
+        let patched = pkgbuild::Patch {
+            source: source.to_owned(),
+            wlroots: self.args.wlroots.clone(),
+            depends: self.args.add_depends.clone(),
+            makedepends: self.args.add_makedepends.clone(),
+            signed: self.args.tag.is_some() && !self.args.no_verify,
+            valid_keys: self.args.valid_keys.clone(),
+            version_remote: pkgbuild::version_remote(self.args.version_source.as_deref()),
         }
+        .apply(&lines);
+        if !patched.missing.is_empty() {
+            let anchors = patched.missing.join(", ");
+            if !self.args.allow_unpatched {
+                return Err(UpdateError::PkgbuildAnchorsMissing { anchors });
+            }
+            log::warn!("PKGBUILD has no {anchors} line(s), building it partly unpatched");
+        }
+        std::fs::write(self.build_path.join("PKGBUILD"), patched.pkgbuild)
+            .map_err(UpdateError::PkgbuildPatchFailed)?;
         Ok(())
     }
 
-    fn modify_pkgbuild(&self) -> anyhow::Result<()> {
-        log::info!("modifying PKGBUILD");
-        let lines = std::fs::read_to_string(self.repo_path.join("PKGBUILD"));
-        match lines {
-            Ok(lines) => {
-                let mut lines = lines
-                    .split_inclusive('\n')
-                    .map(|s| s.to_owned())
-                    .collect::<Vec<String>>();
-                let license = Regex::new(r"license=\(.*\)").unwrap();
-                let source = Regex::new(r"source=\(.*\)").unwrap();
-                let cd = Regex::new(r".*cd qtile").unwrap();
-                let describe = Regex::new(r".*git describe").unwrap();
-                for (index, line) in lines.clone().into_iter().enumerate() {
-                    if license.is_match(&line) {
-                        lines.insert(index + 1, "groups=('modified')\n".to_owned());
-                    }
-                    if source.is_match(&line) {
-                        let source = self.get_source();
-                        let inserted = format!("source=('git+{source}')\n");
-                        lines[index + 1] = inserted;
-                    }
-                    //if Regex::new(r".*build\(\).*").unwrap().is_match(&line) {
-                    //    lines.insert(
-                    //        index + 3,
-                    //        "  export CFLAGS=\"$CFLAGS -I/usr/include/wlroots0.17\"\n".to_owned(),
-                    //    );
-                    //    lines.insert(
-                    //        index + 4,
-                    //        "  export LDFLAGS=\"$LDFLAGS -L/usr/lib/wlroots0.17\"\n".to_owned(),
-                    //    );
-                    //}
-                    if cd.is_match(&line) && describe.is_match(&lines[index + 2]) {
-                        lines.insert(
-                            index + 2,
-                            "  git remote add upstream https://github.com/qtile/qtile.git\n"
-                                .to_owned(),
-                        );
-                        lines.insert(
-                            index + 3,
-                            "  git fetch upstream --tags --force\n".to_owned(),
-                        );
-                    }
-                }
-                let lines = lines.concat();
-                match std::fs::write(self.repo_path.join("PKGBUILD"), lines) {
-                    Ok(()) => {}
-                    Err(err) => {
-                        error_and_exit(&format!("{}\n{}", &"could not write to PKGBUILD", err))
-                    }
-                }
+    fn open_log(&self, dir: &Path) -> std::io::Result<std::fs::File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("install.log"))
+    }
+
+    /// The date of the commit being built, so that builds of the same commit
+    /// are reproducible.
+    fn source_date_epoch(&self, source: &str) -> Option<i64> {
+        match git::source_commit_time(source) {
+            Ok(Some(time)) => Some(time),
+            Ok(None) => {
+                log::warn!("pass the full commit hash to build with SOURCE_DATE_EPOCH set");
+                None
             }
-            Err(err) => error_and_exit(&err.to_string()),
+            Err(err) => {
+                log::warn!(
+                    "could not get the commit date, building without SOURCE_DATE_EPOCH: {err}"
+                );
+                None
+            }
+        }
+    }
+
+    fn build(&self, source: &str) -> Result<()> {
+        if let Some((installed, available)) = python::pending_upgrade()? {
+            log::warn!(
+                "python {available} is available but {installed} is installed, qtile will be built for {installed} and break after the upgrade, consider a full system upgrade first"
+            );
+        }
+        let log_path = self.build_path.join("install.log");
+        let epoch = self.source_date_epoch(source);
+        let staging = if escalate::is_root() {
+            Some(self.stage_for_build_user()?)
+        } else {
+            None
+        };
+        let work_dir = staging.as_ref().map_or_else(
+            || self.build_path.to_path_buf(),
+            |staging| staging.path().to_path_buf(),
+        );
+        let relocated = match &staging {
+            Some(staging) => staging.path().join(STAGED_BUILD_DIR),
+            None => self.relocated_build_dir.clone(),
+        };
+        let configured = build_space::build_dir(self.args.makepkg_conf.as_deref(), &work_dir);
+        let build_dir = build_space::check(&configured, &relocated, self.args.low_space)?;
+        if staging.is_some() {
+            if let Some(dir) = &build_dir {
+                self.give_to_build_user(dir)?;
+            }
+            let mut writable = vec![build_dir.as_deref().unwrap_or(&configured)];
+            writable.extend(self.args.keyring.as_deref());
+            for path in writable {
+                self.check_build_user_can_write(path)?;
+            }
+        }
+        let mut retried = false;
+        while !self.run_makepkg(epoch, &work_dir, build_dir.as_deref())? {
+            let log = std::fs::read_to_string(&log_path)?;
+            if log.contains("PGP signatures could not be verified") {
+                return Err(UpdateError::SignatureCheckFailed { log_path });
+            }
+            let missing = deps::missing_dependencies(&log);
+            if retried || missing.is_empty() || !self.install_missing(&missing)? {
+                return Err(UpdateError::BuildFailed { log_path });
+            }
+            retried = true;
+            log::info!("retrying the build");
+        }
+        if let Some(staging) = staging {
+            self.unstage(staging.path())?;
+            if self.args.keep_build && build_dir.is_some() {
+                // the relocated build tree is in the staging directory
+                let _ = staging.keep();
+            }
+        }
+        // the cached repo (and the last good package in it) is left
+        // untouched unless the new build succeeds
+        self.replace_cache()?;
+        if self.args.keep_build {
+            let tree = match build_dir.unwrap_or(configured) {
+                dir if dir == work_dir => self.repo_path.join("src"),
+                dir => dir.join("qtile-git"),
+            };
+            log::info!(
+                "build tree kept in {:?}, remove it with `update-qtile clean`",
+                tree
+            );
         }
         Ok(())
     }
 
-    fn remove_file_or_dir_if_exists(&self, path: &str) -> anyhow::Result<()> {
-        if let Ok(true) = std::fs::exists(path) {
-            let filetype = std::fs::metadata(path).unwrap().file_type();
-            if filetype.is_dir() {
-                std::fs::remove_dir_all(path)?;
+    /// Offer to install the dependencies a failed build was missing, returns
+    /// whether they were installed.
+    fn install_missing(&self, missing: &[String]) -> Result<bool> {
+        log::warn!("the build is missing: {}", missing.join(", "));
+        if !term::confirm("Would you like to install them and retry?") {
+            return Ok(false);
+        }
+        let f = self.open_log(&self.build_path)?;
+        let (repo, aur): (Vec<&str>, Vec<&str>) = missing
+            .iter()
+            .map(String::as_str)
+            .partition(|dep| deps::in_repos(dep));
+        if !repo.is_empty() {
+            log::info!("installing {} with pacman", repo.join(", "));
+            let status = process::run(
+                "pacman -S",
+                self.privileged("pacman")?
+                    .args(&["-S", "--needed", "--asdeps", "--noconfirm"])
+                    .args(&repo)
+                    .stderr(Redirection::Merge)
+                    .stdout(f.try_clone()?),
+                self.timeout(Stage::Build),
+            )?;
+            if !status.success() {
+                return Ok(false);
             }
-            if filetype.is_file() {
-                std::fs::remove_file(path)?;
+        }
+        if !aur.is_empty() {
+            let Some(helper) = deps::aur_helper().filter(|_| !escalate::is_root()) else {
+                log::error!("{} must be installed from the AUR", aur.join(", "));
+                return Ok(false);
+            };
+            log::info!("installing {} with {helper}", aur.join(", "));
+            let status = process::run(
+                &format!("{helper} -S"),
+                Exec::cmd(helper)
+                    .args(&["-S", "--needed", "--asdeps", "--noconfirm"])
+                    .args(&aur)
+                    .stderr(Redirection::Merge)
+                    .stdout(f.try_clone()?),
+                self.timeout(Stage::Build),
+            )?;
+            if !status.success() {
+                return Ok(false);
             }
         }
+        Ok(true)
+    }
+
+    /// As root, makepkg runs as the build user, who can't get into root's
+    /// cache: it builds in a copy of the build tree that it owns.
+    fn stage_for_build_user(&self) -> Result<tempfile::TempDir> {
+        log::info!("building as `{}`", self.build_user()?);
+        let staging = tempfile::Builder::new()
+            .prefix(&format!("{STAGING_PREFIX}{}-", std::process::id()))
+            .tempdir_in(STAGING_DIR)?;
+        let copied = Exec::cmd("cp")
+            .arg("-a")
+            .arg(self.build_path.join("."))
+            .arg(staging.path())
+            .join()?
+            .success();
+        if !copied {
+            return Err(UpdateError::BuildFailed {
+                log_path: self.build_path.join("install.log"),
+            });
+        }
+        self.give_to_build_user(staging.path())?;
+        Ok(staging)
+    }
+
+    fn give_to_build_user(&self, path: &Path) -> Result<()> {
+        let exit_status = Exec::cmd("chown")
+            .arg("-R")
+            .arg(format!("{}:", self.build_user()?))
+            .arg(path)
+            .join()?;
+        if !exit_status.success() {
+            return Err(UpdateError::BuildFailed {
+                log_path: self.build_path.join("install.log"),
+            });
+        }
         Ok(())
     }
 
-    fn install(self) -> anyhow::Result<()> {
-        log::info!("building with `makepkg`");
-        match std::fs::File::create(self.repo_path.join("install.log")) {
-            Ok(_) => {
-                let mut f = OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .open(self.repo_path.join("install.log"))
-                    .unwrap();
-                writeln!(
-                    f,
-                    "\n------------------------------- building new package -------------------------------\n"
-                )?;
-                let exit_status = (Exec::cmd("yes")
-                    | Exec::cmd("makepkg")
-                        .args(&["-rsc", "--nocheck"])
-                        .cwd(&self.repo_path)
-                        .stderr(Redirection::Merge))
-                .stdout(
-                    f.try_clone()
-                        .expect("no one is writing to the install log now"),
-                )
+    /// Fail before building if the build user can't write to `path`, or to
+    /// the closest existing parent that makepkg would create it in.
+    fn check_build_user_can_write(&self, path: &Path) -> Result<()> {
+        let user = self.build_user()?;
+        let existing = path.ancestors().find(|dir| dir.exists()).unwrap_or(path);
+        let writable = Exec::cmd("systemd-run")
+            .arg(format!("--uid={user}"))
+            .args(&[
+                "--pipe",
+                "--wait",
+                "--quiet",
+                "--collect",
+                "--",
+                "test",
+                "-w",
+            ])
+            .arg(existing)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge)
+            .capture()?
+            .success();
+        if !writable {
+            return Err(UpdateError::NotWritableByBuildUser {
+                user,
+                path: existing.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Copy what makepkg left in the staging directory back into the build
+    /// tree, the relocated build directory excepted.
+    fn unstage(&self, staging: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(staging)? {
+            let path = entry?.path();
+            if path.file_name() == Some(STAGED_BUILD_DIR.as_ref()) {
+                continue;
+            }
+            let copied = Exec::cmd("cp")
+                .arg("-a")
+                .arg(&path)
+                .arg(&*self.build_path)
                 .join()?
                 .success();
-                match exit_status {
-                    true => {
-                        log::info!("removing old package");
-                        // let f = std::fs::File::create(self.repo_path.join("install.log")).unwrap();
-                        writeln!(f, "\n------------------------------- removing old package -------------------------------\n")?;
-
-                        if Exec::cmd("sudo")
-                            .args(&["pacman", "-Qq", "qtile-git"])
-                            .cwd(&self.repo_path)
-                            .stderr(Redirection::Merge)
-                            .stdout(
-                                f.try_clone()
-                                    .expect("no one is writing to the install log now"),
-                            )
-                            .join()?
-                            .success()
-                        {
-                            // let f =
-                            //     std::fs::File::create(self.repo_path.join("install.log")).unwrap();
-                            // let exit_status = (Exec::cmd("yes")
-                            //     | Exec::cmd("sudo")
-                            //         .args(&["pacman", "-Rns", "qtile-git"])
-                            //         .cwd(&self.repo_path)
-                            //         .stderr(Redirection::Merge))
-                            // .stdout(f)
-                            // .join()?
-                            // .success();
-                            // match exit_status {
-                            //     true => {}
-                            //     false => error_and_exit(
-                            //         format!(
-                            //             "Qtile uninstall failed, check in {}/install.log",
-                            //             &self.repo_path.to_str().unwrap()
-                            //         )
-                            //         .as_str(),
-                            //     ),
-                            // }
-                        } else {
-                            let to_be_deleted = [
-                                "/usr/bin/qtile",
-                                "/usr/lib/python3.12/site-packages/libqtile",
-                                "/usr/share/doc/qtile-git",
-                                "/usr/share/licenses/qtile-git/LICENSE",
-                                "/usr/share/wayland-sessions/qtile-wayland.desktop",
-                                "/usr/share/xsessions/qtile.desktop",
-                            ];
-                            for s in to_be_deleted {
-                                self.remove_file_or_dir_if_exists(s)?;
-                            }
-                        }
-                        log::info!("installing new package");
-                        writeln!(f, "\n------------------------------- installing new package -------------------------------\n")?;
-                        let exit_status = (Exec::cmd("yes")
-                            | Exec::cmd("sudo")
-                                .args(&[
-                                    "pacman",
-                                    "-U",
-                                    glob::glob(
-                                        format!(
-                                            "{}/{}",
-                                            self.repo_path.to_str().unwrap(),
-                                            "*.tar.zst"
-                                        )
-                                        .as_str(),
-                                    )
-                                    .unwrap()
-                                    .next()
-                                    .unwrap()
-                                    .unwrap()
-                                    .to_str()
-                                    .expect("package built successfully"),
-                                    "--overwrite",
-                                    "'*'",
-                                ])
-                                .cwd(&self.repo_path)
-                                .stderr(Redirection::Merge))
-                        .stdout(
-                            f.try_clone()
-                                .expect("no one is writing to the install log now"),
-                        )
-                        .join()?
-                        .success();
-                        match exit_status {
-                            true => {}
-                            false => log::error!(
-                                "Qtile install failed, check in {}/install.log",
-                                &self.repo_path.to_str().unwrap()
-                            ),
-                        }
-                        writeln!(f, "\n------------------------------- package installed successfully -------------------------------")?;
-                        if self.args.restart {
-                            log::info!("restarting");
-                            let response = InteractiveCommandClient::call(
-                                Some(vec![]),
-                                Some("restart".to_owned()),
-                                Some(vec![]),
-                                false,
-                            );
-                            match response {
-                                Ok(r) => match r {
-                                    serde_json::Value::Null => {}
-                                    serde_json::Value::Bool(_)
-                                    | serde_json::Value::Number(_)
-                                    | serde_json::Value::String(_)
-                                    | serde_json::Value::Array(_)
-                                    | serde_json::Value::Object(_) => {
-                                        error_and_exit("restart failed, please restart manually");
-                                    }
-                                },
-                                Err(err) => error_and_exit(
-                                    (err.to_string() + "\nQtile is probably not running").as_str(),
-                                ),
-                            }
-                        } else {
-                            log::info!("please restart qtile");
-                        }
+            if !copied {
+                return Err(UpdateError::BuildFailed {
+                    log_path: self.build_path.join("install.log"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run makepkg in `dir`, `build_dir` overrides BUILDDIR.
+    fn run_makepkg(
+        &self,
+        epoch: Option<i64>,
+        dir: &Path,
+        build_dir: Option<&Path>,
+    ) -> Result<bool> {
+        log::info!("building with `makepkg`");
+        let mut f = std::fs::File::create(self.build_path.join("install.log"))?;
+        writeln!(
+            f,
+            "\n------------------------------- building new package -------------------------------\n"
+        )?;
+        let mut flags = vec!["--nocheck"];
+        if escalate::is_root() {
+            self.install_build_deps(dir, &f)?;
+        } else {
+            flags.extend(["-r", "-s"]);
+        }
+        if !self.args.keep_build {
+            flags.push("-c");
+        }
+        let exit_status = process::run_pipeline(
+            "makepkg",
+            (Exec::cmd("yes")
+                | self
+                    .makepkg_with_env(
+                        dir,
+                        epoch
+                            .map(|epoch| ("SOURCE_DATE_EPOCH", epoch.to_string()))
+                            .into_iter()
+                            .chain(build_dir.map(|dir| ("BUILDDIR", dir.display().to_string())))
+                            .collect(),
+                    )?
+                    .args(&flags)
+                    .stderr(Redirection::Merge))
+            .stdout(f),
+            self.timeout(Stage::Build),
+            self.estimate(Stage::Build),
+        )?
+        .success();
+        Ok(exit_status)
+    }
+
+    /// The old package is replaced by `pacman -U` in the same transaction
+    /// that installs the new one, so that a failure can't leave qtile
+    /// uninstalled. Files left behind by an earlier manual install are
+    /// overwritten in that transaction too.
+    fn remove_old(&self) -> Result<()> {
+        let leftovers = self.leftovers(&self.package_path()?)?;
+        if !leftovers.is_empty() {
+            log::info!(
+                "{} file(s) of the new package exist but belong to no package, they will be overwritten",
+                leftovers.len()
+            );
+            for path in &leftovers {
+                log::debug!("  {path}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Files of `package` already on disk that no installed package owns.
+    fn leftovers(&self, package: &Path) -> Result<Vec<String>> {
+        files::unowned(&files::package_files(package)?)
+    }
+
+    /// makepkg refuses to run as root, so as root it runs as the build user.
+    fn makepkg(&self, dir: &Path) -> Result<Exec> {
+        self.makepkg_with_env(dir, vec![])
+    }
+
+    fn makepkg_with_env(&self, dir: &Path, mut env: Vec<(&str, String)>) -> Result<Exec> {
+        if let Some(packager) = &self.args.packager {
+            env.push(("PACKAGER", packager.clone()));
+        }
+        // used by `makepkg -s` to install dependencies, unless makepkg.conf sets
+        // it, so that it escalates like the other stages
+        if let Ok(escalate) = self.escalate() {
+            env.push(("PACMAN_AUTH", escalate.program().to_owned()));
+        }
+        // the keyring makepkg verifies signed sources against
+        if let Some(keyring) = &self.args.keyring {
+            env.push(("GNUPGHOME", keyring.display().to_string()));
+        }
+        let mut exec = if escalate::is_root() {
+            // the unit doesn't inherit our environment
+            Exec::cmd("systemd-run")
+                .arg(format!("--uid={}", self.build_user()?))
+                .arg(format!("--working-directory={}", dir.display()))
+                .args(&["--pipe", "--wait", "--quiet", "--collect"])
+                .args(
+                    &env.iter()
+                        .map(|(name, value)| format!("--setenv={name}={value}"))
+                        .collect::<Vec<_>>(),
+                )
+                .args(&["--", "makepkg"])
+        } else {
+            env.iter()
+                .fold(Exec::cmd("makepkg"), |exec, (name, value)| {
+                    exec.env(name, value)
+                })
+        }
+        .cwd(dir);
+        if let Some(conf) = &self.args.makepkg_conf {
+            exec = exec.arg("--config").arg(conf);
+        }
+        Ok(exec)
+    }
+
+    /// As root, dependencies are installed up front since the build user
+    /// can't do it through `makepkg -s`.
+    fn install_build_deps(&self, dir: &Path, f: &std::fs::File) -> Result<()> {
+        let srcinfo = self
+            .makepkg(dir)?
+            .arg("--printsrcinfo")
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()?
+            .stdout_str();
+        let deps = srcinfo
+            .lines()
+            .filter_map(|line| line.trim().split_once(" = "))
+            .filter(|(key, _)| ["depends", "makedepends"].contains(key))
+            .map(|(_, dep)| deps::strip_version(dep))
+            .collect::<Vec<_>>();
+        if deps.is_empty() {
+            return Ok(());
+        }
+        log::info!("installing build dependencies");
+        let exit_status = process::run(
+            "pacman -S",
+            Exec::cmd("pacman")
+                .args(&["-S", "--needed", "--asdeps", "--noconfirm"])
+                .args(&deps)
+                .stderr(Redirection::Merge)
+                .stdout(f.try_clone()?),
+            self.timeout(Stage::Build),
+        )?;
+        if !exit_status.success() {
+            return Err(UpdateError::BuildFailed {
+                log_path: self.build_path.join("install.log"),
+            });
+        }
+        Ok(())
+    }
+
+    /// The built package, wherever PKGDEST and PKGEXT put it.
+    fn package_path(&self) -> Result<PathBuf> {
+        let capture = self
+            .makepkg(&self.repo_path)?
+            .arg("--packagelist")
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()?;
+        let listed = capture
+            .stdout_str()
+            .lines()
+            .map(PathBuf::from)
+            .find(|path| path.exists());
+        let globbed = || {
+            let pattern = self.repo_path.join("*.pkg.tar*");
+            glob::glob(pattern.to_str()?).ok()?.flatten().next()
+        };
+        listed
+            .or_else(globbed)
+            .ok_or_else(|| UpdateError::PackageNotFound {
+                dir: self.repo_path.to_path_buf(),
+            })
+    }
+
+    fn diff_files(&self, package: &Path) -> Result<()> {
+        match files::installed_files("qtile-git")? {
+            Some(installed) => files::log_diff(&installed, &files::package_files(package)?),
+            None => log::info!("qtile-git is not installed, nothing to compare against"),
+        }
+        Ok(())
+    }
+
+    /// Install the built package, `sha256` is its checksum right after the
+    /// build.
+    fn install(&self, sha256: Option<&str>) -> Result<()> {
+        let mut f = self.open_log(&self.repo_path)?;
+        log::info!("installing new package");
+        writeln!(f, "\n------------------------------- installing new package -------------------------------\n")?;
+        let package = self.package_path()?;
+        if !self.args.no_file_diff {
+            self.diff_files(&package)?;
+        }
+        let backups = self.backup_sessions(&package)?;
+        let sha256 = match sha256 {
+            Some(sha256) => Some(sha256.to_owned()),
+            None => self.recorded_sha256(&package)?,
+        };
+        match sha256 {
+            Some(sha256) => cache::verify(&package, &sha256)?,
+            None if self.args.allow_unverified => log::warn!(
+                "no checksum was recorded for {:?}, not verifying it",
+                package
+            ),
+            None => return Err(UpdateError::Unverified { path: package }),
+        }
+        let log_path = self.repo_path.join("install.log");
+        let mut overwrite = self.leftovers(&package)?;
+        let mut retried = false;
+        loop {
+            let start = f.metadata()?.len() as usize;
+            if self.run_pacman_install(&package, &overwrite, &f)? {
+                break;
+            }
+            let log = std::fs::read(&log_path)?;
+            let output = String::from_utf8_lossy(log.get(start..).unwrap_or_default());
+            let conflicts = conflicts::file_conflicts(&output);
+            // only retry once, with the files pacman complained about
+            if conflicts.is_empty() || retried {
+                return Err(UpdateError::InstallFailed { log_path });
+            }
+            let resolved = self.resolve_conflicts(&conflicts)?;
+            if resolved.is_empty() {
+                return Err(UpdateError::InstallFailed { log_path });
+            }
+            overwrite.extend(resolved);
+            retried = true;
+        }
+        writeln!(f, "\n------------------------------- package installed successfully -------------------------------")?;
+        self.restore_sessions(&backups)
+    }
+
+    /// The checksum the latest build of `package` recorded, for runs that
+    /// didn't build it themselves, e.g. `--from-stage install`.
+    fn recorded_sha256(&self, package: &Path) -> Result<Option<String>> {
+        let history = History::load(&self.history_path)?;
+        Ok(history
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| {
+                entry.package.as_deref().and_then(Path::file_name) == package.file_name()
+            })
+            .find_map(|entry| entry.sha256.clone()))
+    }
+
+    /// Save the session files the new package would replace if they were
+    /// customized.
+    fn backup_sessions(&self, package: &Path) -> Result<Vec<(String, PathBuf)>> {
+        let sessions = sessions::session_files(&files::package_files(package)?, self.args.backend);
+        let customized = sessions::customized(&sessions)?;
+        sessions::backup(&customized, &self.sessions_backup_dir)
+    }
+
+    fn restore_sessions(&self, backups: &[(String, PathBuf)]) -> Result<()> {
+        for (path, backup) in backups {
+            let exit_status = self
+                .privileged("install")?
+                .args(&["-m", "644"])
+                .arg(backup)
+                .arg(path)
+                .join()?;
+            if exit_status.success() {
+                log::info!("restored customized {path}");
+            } else {
+                log::warn!(
+                    "could not restore {path}, the customized one is in {:?}",
+                    backup
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn run_pacman_install(
+        &self,
+        package: &Path,
+        overwrite: &[String],
+        f: &std::fs::File,
+    ) -> Result<bool> {
+        let overwrite = overwrite
+            .iter()
+            .flat_map(|path| ["--overwrite", path.as_str()]);
+        let exit_status = process::run_pipeline(
+            "pacman -U",
+            (Exec::cmd("yes")
+                | self
+                    .privileged("pacman")?
+                    .arg("-U")
+                    .arg(package)
+                    .args(&overwrite.collect::<Vec<_>>())
+                    .cwd(&self.repo_path)
+                    .stderr(Redirection::Merge))
+            .stdout(
+                f.try_clone()
+                    .expect("no one is writing to the install log now"),
+            ),
+            self.timeout(Stage::Install),
+            None,
+        )?;
+        Ok(exit_status.success())
+    }
+
+    /// Show which packages own the conflicting files and offer to overwrite
+    /// the ones no package owns. Returns the paths to pass to `--overwrite`,
+    /// empty to give up.
+    fn resolve_conflicts(&self, conflicts: &[conflicts::Conflict]) -> Result<Vec<String>> {
+        log::error!("pacman found {} conflicting file(s):", conflicts.len());
+        for conflict in conflicts {
+            match &conflict.owner {
+                Some(owner) => log::error!("  {} (owned by {owner})", conflict.path),
+                None => log::error!("  {} (not owned by any package)", conflict.path),
+            }
+        }
+        let mut owners = conflicts
+            .iter()
+            .filter_map(|conflict| conflict.owner.as_deref())
+            .collect::<Vec<_>>();
+        if !owners.is_empty() {
+            owners.sort();
+            owners.dedup();
+            log::error!(
+                "remove or update {} before installing qtile-git",
+                owners.join(", ")
+            );
+            return Ok(vec![]);
+        }
+        if !term::confirm("These files are leftovers, would you like to overwrite them and retry?")
+        {
+            return Ok(vec![]);
+        }
+        Ok(conflicts
+            .iter()
+            .map(|conflict| conflict.path.clone())
+            .collect())
+    }
+
+    /// A package built for another Python than the system one can't be
+    /// imported, restarting into it would leave the session without a WM.
+    fn check_python(&self) -> Result<()> {
+        let Some(files) = files::installed_files("qtile-git")? else {
+            return Ok(());
+        };
+        let (Some(package), Some(interpreter)) = (
+            python::package_version(&files),
+            python::interpreter_version()?,
+        ) else {
+            return Ok(());
+        };
+        if package != interpreter {
+            return Err(UpdateError::PythonMismatch {
+                package,
+                interpreter,
+            });
+        }
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        if self.args.restart || self.args.restart_on_login {
+            self.check_python()?;
+        }
+        if self.args.restart {
+            if let Some(minutes) = self.args.restart_when_idle {
+                idle::wait_until_idle(Duration::from_secs(minutes * 60))?;
+            }
+            if self.args.preserve_layout {
+                log::info!("saving window layout to {:?}", self.layout_path);
+                session::Layout::capture()?.save(&self.layout_path)?;
+            }
+            log::info!("restarting");
+            ipc::restart()?;
+            if self.args.preserve_layout {
+                session::Layout::load(&self.layout_path)?.restore()?;
+            }
+        } else if self.args.restart_on_login {
+            if let Some(parent) = self.pending_login_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let version = status::installed_version()?.unwrap_or_default();
+            std::fs::write(&self.pending_login_path, version)?;
+            log::info!("the new version will be used at the next login");
+        } else {
+            log::info!("please restart qtile");
+        }
+        Ok(())
+    }
+
+    /// The PKGBUILD was rewritten by us, so namcap errors in it are fatal.
+    fn lint_pkgbuild(&self) -> Result<()> {
+        if !self.args.lint {
+            return Ok(());
+        }
+        let pkgbuild = self.build_path.join("PKGBUILD");
+        match lint::namcap(&pkgbuild)? {
+            0 => Ok(()),
+            errors => Err(UpdateError::LintFailed {
+                path: pkgbuild,
+                errors,
+            }),
+        }
+    }
+
+    fn lint_package(&self) -> Result<()> {
+        if self.args.lint {
+            lint::namcap(&self.package_path()?)?;
+        }
+        Ok(())
+    }
+
+    /// Startup time of the installed qtile, `None` when not benchmarking or
+    /// when it can't be measured, which doesn't fail the update.
+    fn benchmark(&self, which: &str) -> Option<Duration> {
+        if !self.args.benchmark {
+            return None;
+        }
+        log::info!("measuring the startup time of the {which} qtile");
+        let config = match &self.args.benchmark_config {
+            Some(config) => Ok(config.clone()),
+            None => bench::default_config(),
+        };
+        match config.and_then(|config| bench::startup_time(&config)) {
+            Ok(duration) => Some(duration),
+            Err(err) => {
+                log::warn!("{err}");
+                None
+            }
+        }
+    }
+
+    /// Look for tracebacks the restarted qtile logged and suggest fixes.
+    fn check_crash_log(&self, offset: u64, entry: &mut history::Entry) {
+        let path = crashlog::log_path();
+        let tracebacks = crashlog::watch(&path, offset);
+        if tracebacks.is_empty() {
+            log::info!("no errors in the qtile log after restarting");
+            return;
+        }
+        for traceback in &tracebacks {
+            log::error!("qtile logged an error after restarting:\n{traceback}");
+            if let Some(suggestion) = crashlog::suggestion(traceback) {
+                log::warn!("suggestion: {suggestion}");
+            }
+        }
+        entry.crash_log = Some(tracebacks.join("\n\n"));
+    }
+
+    fn run_stage(&self, stage: Stage, state: &RunState, entry: &mut history::Entry) -> Result<()> {
+        match stage {
+            Stage::Clean => self.remove_stale_build(),
+            Stage::Clone => self.clone_repo(),
+            Stage::Patch => {
+                self.modify_pkgbuild(&state.source)?;
+                self.lint_pkgbuild()
+            }
+            Stage::Build => {
+                self.build(&state.source)?;
+                self.lint_package()
+            }
+            Stage::RemoveOld => self.remove_old(),
+            Stage::Install => {
+                db_lock::wait_unlocked(self.args.wait_for_pacman)?;
+                let before = status::installed_version()?.and_then(|_| self.benchmark("installed"));
+                self.install(state.package_sha256.as_deref())?;
+                if let Some(before) = before {
+                    if let Some(after) = self.benchmark("new") {
+                        bench::log_delta(before, after);
                     }
-                    false => log::error!(
-                        "Qtile build failed, check in {}/install.log",
-                        &self.repo_path.to_str().unwrap()
-                    ),
                 }
+                Ok(())
+            }
+            Stage::Restart => {
+                let offset = crashlog::log_len(&crashlog::log_path());
+                self.restart()?;
+                if self.args.restart {
+                    self.check_crash_log(offset, entry);
+                }
+                Ok(())
             }
-            Err(_) => todo!(),
         }
+    }
+
+    /// Note the abort in the install log. Completed stages are already saved,
+    /// so the run can be picked up again with `--resume`.
+    fn record_abort(&self, stage: Stage) {
+        let dir = if stage <= Stage::Build {
+            &self.build_path
+        } else {
+            &self.repo_path
+        };
+        if let Ok(mut f) = self.open_log(dir) {
+            let _ = writeln!(
+                f,
+                "\n------------------------------- aborted during stage `{}` -------------------------------",
+                stage.name()
+            );
+        }
+        log::info!("run `update-qtile update --resume` to continue");
+    }
+
+    /// Cache the built package outside of the build tree, which the next clone
+    /// replaces, and note it and its checksum in the history entry.
+    fn keep_package(&self, state: &mut RunState, entry: &mut history::Entry) -> Result<()> {
+        let package = self.package_path()?;
+        let hash = cache::sha256(&package)?;
+        let kept = cache::store(&package, &hash, &self.packages_dir)?;
+        state.package_sha256 = Some(hash.clone());
+        entry.sha256 = Some(hash);
+        if entry.commit.is_none() {
+            entry.commit = history::commit_from_package(&kept);
+        }
+        entry.package = Some(kept);
+        entry.pkgbuild = Some(std::fs::read_to_string(self.repo_path.join("PKGBUILD"))?);
         Ok(())
     }
+
+    fn run_stages(
+        &self,
+        first: Stage,
+        last: Stage,
+        state: &mut RunState,
+        entry: &mut history::Entry,
+    ) -> Result<()> {
+        for stage in Stage::ALL
+            .into_iter()
+            .filter(|stage| (first..=last).contains(stage))
+        {
+            log::debug!("running stage `{}`", stage.name());
+            let start = Instant::now();
+            let result = self.run_stage(stage, state, entry);
+            entry.stages.push((stage, start.elapsed().as_secs()));
+            if let Err(err) = result {
+                if process::interrupted() {
+                    self.record_abort(stage);
+                }
+                return Err(err);
+            }
+            if stage == Stage::Build {
+                self.keep_package(state, entry)?;
+            }
+            state.completed = Some(stage);
+            state.save(&self.state_path)?;
+        }
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        let _lock = RunLock::acquire(&self.lock_path)?;
+        let last = self.args.until_stage.unwrap_or(Stage::Restart);
+        let check_range = |first: Stage| {
+            if first > last {
+                Err(UpdateError::InvalidStageRange {
+                    first: first.name(),
+                    last: last.name(),
+                })
+            } else {
+                Ok(())
+            }
+        };
+        // before resolving the source, which goes over the network
+        if let Some(first) = self.args.from_stage {
+            check_range(first)?;
+        }
+        let mut prefetched = None;
+        let mut state = if self.args.resume {
+            let Some(state) = RunState::load(&self.state_path)? else {
+                return Err(UpdateError::NothingToResume);
+            };
+            log::info!("resuming run for `{}`", state.source);
+            state
+        } else if self.args.from_stage.is_none() && last >= Stage::Clone {
+            let (source, fetched) = self.prefetch()?;
+            prefetched = Some(fetched);
+            RunState::new(source)
+        } else if last < Stage::Clone || self.args.from_stage > Some(Stage::Build) {
+            // only cloning, patching and building use the resolved source
+            RunState::new(git::remote_url(
+                self.args.fork.as_deref(),
+                self.args.path.as_deref(),
+            ))
+        } else {
+            RunState::new(self.get_source()?)
+        };
+        let first = match self.args.from_stage {
+            Some(stage) => stage,
+            None => match state.next_stage() {
+                Some(stage) => stage,
+                None => return RunState::clear(&self.state_path),
+            },
+        };
+        check_range(first)?;
+        // find out now rather than after a long build
+        if self.args.restart && first < Stage::Restart && last == Stage::Restart {
+            if let Err(err) = ipc::call(&[], "status", &[]) {
+                log::warn!(
+                    "{err}, the restart after installing will probably fail (see --check-restart)"
+                );
+            }
+        }
+        let mut history = History::load(&self.history_path)?;
+        let mut entry = history::Entry::new(history.next_id(), state.source.clone());
+        let result = match prefetched {
+            Some(Ok(timings)) => {
+                entry.stages.extend(timings);
+                state.completed = Some(Stage::Clone);
+                state.save(&self.state_path)?;
+                self.run_stages(Stage::Patch, last, &mut state, &mut entry)
+            }
+            Some(Err(err)) => {
+                if process::interrupted() {
+                    self.record_abort(Stage::Clone);
+                }
+                Err(err)
+            }
+            None => self.run_stages(first, last, &mut state, &mut entry),
+        };
+        entry.finish(&result);
+        history.entries.push(entry);
+        history.save(&self.history_path)?;
+        result?;
+        if last < Stage::Restart {
+            let dir = if last < Stage::Build {
+                &self.build_path
+            } else {
+                &self.repo_path
+            };
+            log::info!(
+                "stopped after stage `{}`, build directory is {:?}",
+                last.name(),
+                dir
+            );
+            return Ok(());
+        }
+        RunState::clear(&self.state_path)
+    }
+}
+
+/// Where root builds are staged for the build user.
+const STAGING_DIR: &str = "/var/tmp";
+const STAGING_PREFIX: &str = "update-qtile-";
+/// The run a staging directory, named `update-qtile-<pid>-<random>`, belongs
+/// to.
+fn staging_pid(staging: &Path) -> Option<u32> {
+    let name = staging
+        .file_name()?
+        .to_str()?
+        .strip_prefix(STAGING_PREFIX)?;
+    name.split_once('-')?.0.parse().ok()
 }
+
+/// BUILDDIR of a relocated root build, inside its staging directory.
+const STAGED_BUILD_DIR: &str = "builddir";
+
+/// How long each stage took, in seconds.
+type StageTimings = Vec<(Stage, u64)>;
+
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigCommand {
+    /// Print the effective options and where each one comes from
+    Show(Box<UpdateArgs>),
+    /// Edit the config file in $EDITOR, it is only saved if it is valid
+    Edit,
+}
+
+fn update(mut args: UpdateArgs, matches: &ArgMatches) -> Result<()> {
+    config::apply_profile(&mut args, matches)?;
+    if args.interactive || args.branch.as_deref() == Some("") {
+        let url = git::remote_url(args.fork.as_deref(), args.path.as_deref());
+        args.branch = None;
+        match pick::pick_target(&url)? {
+            pick::Target::Branch(branch) => args.branch = Some(branch),
+            pick::Target::Tag(tag) => args.tag = Some(tag),
+            pick::Target::Commit(commit) => args.commit = Some(commit),
+        }
+    }
+    if args.check_restart {
+        return ipc::check_restart();
+    }
+    if let Some(commits) = args
+        .commit
+        .as_deref()
+        .map(batch::Commits::parse)
+        .transpose()?
+        .flatten()
+    {
+        return batch::batch(args, commits, &xdg::cache_home());
+    }
+    UpdateQtile::new(args).run()
+}
+
 fn main() {
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Info)
+        .with_colors(term::colors())
         .env()
         .init()
         .unwrap();
-    let args = Args::parse();
-    let up = UpdateQtile::new(args);
-    match up.remove_repo() {
-        Ok(()) => match up.clone_repo() {
-            Ok(()) => match up.install() {
-                Ok(()) => {}
-                Err(err) => {
-                    error_and_exit(&err.to_string());
-                }
-            },
-            Err(err) => {
-                error_and_exit(&err.to_string());
+    if let Err(err) = process::install_signal_handler() {
+        log::warn!("could not install signal handler: {err}");
+    }
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let result = match cli.command {
+        Some(Command::Update(args)) => update(
+            *args,
+            matches
+                .subcommand_matches("update")
+                .expect("update subcommand was given"),
+        ),
+        Some(Command::Clean { escalate }) => UpdateQtile::new(UpdateArgs {
+            escalate,
+            // to find where BUILDDIR points
+            makepkg_conf: std::env::var_os("MAKEPKG_CONF").map(PathBuf::from),
+            ..Default::default()
+        })
+        .clean(),
+        Some(Command::Refs(args)) => refs::refs(&args),
+        Some(Command::SelfUpdate(args)) => self_update::self_update(&args),
+        Some(Command::Check(args)) => check::check(&args).map(|available| {
+            if available {
+                exit(check::UPDATE_AVAILABLE)
             }
-        },
-        Err(err) => {
-            error_and_exit(&err.to_string());
+        }),
+        Some(Command::Status) => status::status(&xdg::cache_home()),
+        Some(Command::Verify) => verify::verify(),
+        Some(Command::Log(args)) => {
+            let update = UpdateQtile::new(UpdateArgs::default());
+            build_log::log(&args, &[&update.repo_path, &update.build_path])
+        }
+        Some(Command::History(args)) => history::history(&args, &xdg::cache_home()),
+        Some(Command::DiffBuilds(args)) => diff_builds::diff_builds(&args, &xdg::cache_home()),
+        Some(Command::Deploy(args)) => deploy::deploy(&args, &xdg::cache_home()),
+        Some(Command::Config {
+            command: ConfigCommand::Show(args),
+        }) => config::show(
+            *args,
+            matches
+                .subcommand_matches("config")
+                .and_then(|matches| matches.subcommand_matches("show"))
+                .expect("config show subcommand was given"),
+        ),
+        Some(Command::Config {
+            command: ConfigCommand::Edit,
+        }) => config::edit(),
+        Some(Command::Cache(args)) => {
+            let update = UpdateQtile::new(UpdateArgs::default());
+            cache::cache(
+                &args,
+                &update.packages_dir,
+                &update.history_path,
+                &[&update.repo_path, &update.build_path],
+            )
         }
+        None => update(cli.update, &matches),
+    };
+    if let Err(err) = result {
+        log::error!("{err}");
+        exit(err.exit_code());
     }
 }
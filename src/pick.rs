@@ -0,0 +1,51 @@
+use dialoguer::FuzzySelect;
+
+use crate::error::{Result, UpdateError};
+use crate::git;
+use crate::term;
+
+/// How many commits of the default branch are offered.
+const RECENT_COMMITS: usize = 50;
+
+pub enum Target {
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+/// Let the user fuzzy-search the branches, tags and recent commits of `url`.
+pub fn pick_target(url: &str) -> Result<Target> {
+    if !term::interactive() {
+        return Err(UpdateError::NoTerminal(
+            "picking the build target".to_owned(),
+        ));
+    }
+    log::info!("fetching refs of `{url}`");
+    let refs = git::ls_remote(url)?;
+    let commits = git::recent_commits(url, RECENT_COMMITS)?;
+    let mut items = vec![];
+    let mut targets = vec![];
+    for r in &refs {
+        if let Some(branch) = r.branch() {
+            items.push(format!("branch  {branch}"));
+            targets.push(Target::Branch(branch.to_owned()));
+        }
+    }
+    for r in refs.iter().rev() {
+        if let Some(tag) = r.tag() {
+            items.push(format!("tag     {tag}"));
+            targets.push(Target::Tag(tag.to_owned()));
+        }
+    }
+    for (oid, summary) in commits {
+        let sha = oid.to_string();
+        items.push(format!("commit  {} {summary}", &sha[..10]));
+        targets.push(Target::Commit(sha));
+    }
+    let index = FuzzySelect::new()
+        .with_prompt("Build target")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    Ok(targets.swap_remove(index))
+}
@@ -0,0 +1,302 @@
+use regex::Regex;
+
+const UPSTREAM_URL: &str = "https://github.com/qtile/qtile.git";
+
+/// The changes made to the AUR PKGBUILD.
+pub struct Patch {
+    /// What goes after `git+` in `source=()`.
+    pub source: String,
+    /// Build against a specific wlroots, e.g. `0.17`.
+    pub wlroots: Option<String>,
+    /// Packages added to `depends=()`.
+    pub depends: Vec<String>,
+    /// Packages added to `makedepends=()`.
+    pub makedepends: Vec<String>,
+    /// Have makepkg verify the signature of the tag or commit.
+    pub signed: bool,
+    /// Fingerprints of the keys allowed to sign it.
+    pub valid_keys: Vec<String>,
+    /// Repo whose tags `git describe` derives pkgver from, `None` for the
+    /// tags of the built repo itself.
+    pub version_remote: Option<String>,
+}
+
+/// Prepend `packages` to the array opened on `line`.
+fn inject(line: &str, array: &str, packages: &[String]) -> String {
+    let quoted = packages
+        .iter()
+        .map(|package| format!("'{package}' "))
+        .collect::<String>();
+    line.replacen(&format!("{array}=("), &format!("{array}=({quoted}"), 1)
+}
+
+/// A patched PKGBUILD and the changes that couldn't be made because the line
+/// they are anchored to wasn't found.
+pub struct Patched {
+    pub pkgbuild: String,
+    pub missing: Vec<&'static str>,
+}
+
+impl Patch {
+    pub fn apply(&self, pkgbuild: &str) -> Patched {
+        let license = Regex::new(r"license=\(.*\)").unwrap();
+        let source = Regex::new(r"source=\(.*\)").unwrap();
+        let depends = Regex::new(r"^depends=\(").unwrap();
+        let makedepends = Regex::new(r"^makedepends=\(").unwrap();
+        let build = Regex::new(r"^build\(\)").unwrap();
+        let cd = Regex::new(r".*cd qtile").unwrap();
+        let describe = Regex::new(r".*git describe").unwrap();
+
+        let mut extra_depends = self.depends.clone();
+        if let Some(version) = &self.wlroots {
+            extra_depends.insert(0, format!("wlroots{version}"));
+        }
+
+        let lines = pkgbuild.split_inclusive('\n').collect::<Vec<_>>();
+        let mut patched = vec![];
+        let mut found = vec![];
+        // the exports go after the opening brace of build(), which may be on
+        // the next line
+        let mut in_build = false;
+        for (index, line) in lines.iter().enumerate() {
+            if source.is_match(line) {
+                found.push("source");
+                let signed = if self.signed { "?signed" } else { "" };
+                patched.push(format!("source=('git+{}{signed}')\n", self.source));
+                if !self.valid_keys.is_empty() {
+                    patched.push(format!(
+                        "validpgpkeys=('{}')\n",
+                        self.valid_keys.join("' '")
+                    ));
+                }
+            } else if depends.is_match(line) {
+                found.push("depends");
+                patched.push(inject(line, "depends", &extra_depends));
+            } else if makedepends.is_match(line) {
+                found.push("makedepends");
+                patched.push(inject(line, "makedepends", &self.makedepends));
+            } else {
+                patched.push(line.to_string());
+            }
+            if license.is_match(line) {
+                found.push("license");
+                patched.push("groups=('modified')\n".to_owned());
+            }
+            in_build |= build.is_match(line);
+            if let Some(version) = self
+                .wlroots
+                .as_ref()
+                .filter(|_| in_build && line.contains('{'))
+            {
+                in_build = false;
+                found.push("build");
+                patched.push(format!(
+                    "  export CFLAGS=\"$CFLAGS -I/usr/include/wlroots{version}\"\n"
+                ));
+                patched.push(format!(
+                    "  export LDFLAGS=\"$LDFLAGS -L/usr/lib/wlroots{version}\"\n"
+                ));
+            }
+            if let Some(remote) = self.version_remote.as_ref().filter(|_| {
+                cd.is_match(line)
+                    && lines
+                        .get(index + 1)
+                        .is_some_and(|next| describe.is_match(next))
+            }) {
+                found.push("describe");
+                patched.push(format!(
+                    "  git remote add upstream {remote} 2>/dev/null || git remote set-url upstream {remote}\n"
+                ));
+                patched.push("  git fetch upstream --tags --force\n".to_owned());
+            }
+        }
+        let mut wanted = vec!["source", "license"];
+        if self.version_remote.is_some() {
+            wanted.push("describe");
+        }
+        if !extra_depends.is_empty() {
+            wanted.push("depends");
+        }
+        if !self.makedepends.is_empty() {
+            wanted.push("makedepends");
+        }
+        if self.wlroots.is_some() {
+            wanted.push("build");
+        }
+        Patched {
+            pkgbuild: patched.concat(),
+            missing: wanted
+                .into_iter()
+                .filter(|anchor| !found.contains(anchor))
+                .collect(),
+        }
+    }
+}
+
+/// Accept `fork`, `upstream` or the URL of a repo to take the version tags
+/// from.
+pub fn parse_version_source(s: &str) -> Result<String, String> {
+    if ["fork", "upstream"].contains(&s) || s.contains("://") || s.starts_with("git@") {
+        Ok(s.to_owned())
+    } else {
+        Err(format!("expected `fork`, `upstream` or a URL, got `{s}`"))
+    }
+}
+
+/// The remote to fetch version tags from for a `--version-source`, qtile's
+/// by default.
+pub fn version_remote(version_source: Option<&str>) -> Option<String> {
+    match version_source {
+        Some("fork") => None,
+        None | Some("upstream") => Some(UPSTREAM_URL.to_owned()),
+        Some(url) => Some(url.to_owned()),
+    }
+}
+
+/// Accept wlroots versions like `0.17`.
+pub fn parse_wlroots_version(s: &str) -> Result<String, String> {
+    let version = s.trim_start_matches('v');
+    if Regex::new(r"^\d+\.\d+$").unwrap().is_match(version) {
+        Ok(version.to_owned())
+    } else {
+        Err(format!("expected a version like `0.17`, got `{s}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The AUR's qtile-git PKGBUILD.
+    const AUR: &str = r#"# Maintainer: Ervin Popescu <ervin.popescu10@gmail.com>
+
+pkgname=qtile-git
+pkgver=0.29.0.r12.gabc1234
+pkgrel=1
+provides=("qtile")
+conflicts=("qtile")
+pkgdesc="A full-featured, pure-Python tiling window manager. (git version)"
+arch=('x86_64')
+url="https://qtile.org"
+license=('MIT')
+depends=('python' 'python-cairocffi' 'python-xcffib' 'pango'
+  'python-dbus-fast' 'python-pywayland' 'python-pywlroots' 'python-xkbcommon')
+makedepends=('git' 'python-build' 'python-installer' 'python-setuptools-scm' 'python-wheel')
+optdepends=('alsa-utils: volume widget'
+  'python-psutil: graph, net and memory widgets')
+source=('git+https://github.com/qtile/qtile.git')
+md5sums=('SKIP')
+
+pkgver()
+{
+  cd qtile
+  git describe --tags | sed 's/-/.r/; s/-g/.g/; s/^v//'
+}
+
+build()
+{
+  cd qtile
+  python -m build --wheel --no-isolation
+}
+
+package()
+{
+  cd qtile
+  python -m installer --destdir="$pkgdir" dist/*.whl
+  install -Dm644 LICENSE "$pkgdir/usr/share/licenses/$pkgname/LICENSE"
+}
+"#;
+
+    fn patch() -> Patch {
+        Patch {
+            source: "https://github.com/me/qtile.git#branch=wip".to_owned(),
+            wlroots: Some("0.17".to_owned()),
+            depends: vec!["python-psutil".to_owned()],
+            makedepends: vec!["python-pip".to_owned()],
+            signed: true,
+            valid_keys: vec!["ABCD".to_owned(), "EF01".to_owned()],
+            version_remote: Some(UPSTREAM_URL.to_owned()),
+        }
+    }
+
+    #[test]
+    fn patches_every_anchor() {
+        let patched = patch().apply(AUR);
+        assert!(patched.missing.is_empty(), "{:?}", patched.missing);
+        for expected in [
+            "source=('git+https://github.com/me/qtile.git#branch=wip?signed')\n",
+            "validpgpkeys=('ABCD' 'EF01')\n",
+            "license=('MIT')\ngroups=('modified')\n",
+            "depends=('wlroots0.17' 'python-psutil' 'python' ",
+            "makedepends=('python-pip' 'git' ",
+            "build()\n{\n  export CFLAGS=\"$CFLAGS -I/usr/include/wlroots0.17\"\n  export LDFLAGS=\"$LDFLAGS -L/usr/lib/wlroots0.17\"\n  cd qtile\n",
+            "pkgver()\n{\n  cd qtile\n  git remote add upstream https://github.com/qtile/qtile.git 2>/dev/null || git remote set-url upstream https://github.com/qtile/qtile.git\n  git fetch upstream --tags --force\n  git describe",
+        ] {
+            assert!(patched.pkgbuild.contains(expected), "{expected}");
+        }
+    }
+
+    #[test]
+    fn patches_build_with_brace_on_the_same_line() {
+        let patched = patch().apply(&AUR.replace("build()\n{", "build() {"));
+        assert!(patched.missing.is_empty(), "{:?}", patched.missing);
+        assert!(patched
+            .pkgbuild
+            .contains("build() {\n  export CFLAGS=\"$CFLAGS -I/usr/include/wlroots0.17\"\n"));
+    }
+
+    #[test]
+    fn patched_pkgbuild_is_valid_bash() {
+        for pkgbuild in [AUR.to_owned(), AUR.replace("build()\n{", "build() {")] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            std::fs::write(file.path(), patch().apply(&pkgbuild).pkgbuild).unwrap();
+            let status = std::process::Command::new("bash")
+                .arg("-n")
+                .arg(file.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn only_patches_what_is_asked() {
+        let patch = Patch {
+            source: UPSTREAM_URL.to_owned(),
+            wlroots: None,
+            depends: vec![],
+            makedepends: vec![],
+            signed: false,
+            valid_keys: vec![],
+            version_remote: None,
+        };
+        let patched = patch.apply(AUR);
+        assert!(patched.missing.is_empty(), "{:?}", patched.missing);
+        assert_eq!(
+            patched.pkgbuild,
+            AUR.replace(
+                "license=('MIT')\n",
+                "license=('MIT')\ngroups=('modified')\n"
+            )
+        );
+    }
+
+    #[test]
+    fn reports_missing_anchors() {
+        for (anchor, line) in [
+            ("source", "source=("),
+            ("license", "license=("),
+            ("depends", "depends=("),
+            ("makedepends", "makedepends=("),
+            ("build", "build()"),
+            ("describe", "  git describe"),
+        ] {
+            let pkgbuild = AUR
+                .lines()
+                .filter(|l| !l.starts_with(line))
+                .map(|l| format!("{l}\n"))
+                .collect::<String>();
+            assert_eq!(patch().apply(&pkgbuild).missing, [anchor], "{anchor}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use subprocess::{Exec, ExitStatus, Pipeline, Popen};
+
+use crate::error::{Result, UpdateError};
+use crate::stage::Stage;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a terminated process gets to exit before it is killed.
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Turn SIGINT/SIGTERM into a flag that running stages check, so children can
+/// be stopped and the run cleaned up. A second signal exits right away.
+pub fn install_signal_handler() -> std::result::Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        log::warn!("interrupted, cleaning up (interrupt again to exit immediately)");
+    })
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Parse durations like `90`, `90s`, `15m` or `1h`.
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+    let seconds = match unit {
+        "s" => Some(number),
+        "m" => number.checked_mul(60),
+        "h" => number.checked_mul(60 * 60),
+        _ => return Err(format!("invalid duration unit `{unit}`, use s, m or h")),
+    };
+    seconds
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration `{s}` is too long"))
+}
+
+/// Parse `STAGE=DURATION` pairs, e.g. `build=30m`.
+pub fn parse_stage_timeout(s: &str) -> std::result::Result<(Stage, Duration), String> {
+    let Some((stage, duration)) = s.split_once('=') else {
+        return Err(format!("expected STAGE=DURATION, got `{s}`"));
+    };
+    Ok((Stage::from_str(stage, true)?, parse_duration(duration)?))
+}
+
+fn stop(process: &mut Popen) {
+    // SIGTERM first: sudo forwards it to its child, it can't forward SIGKILL
+    let _ = process.terminate();
+    if let Ok(None) = process.wait_timeout(GRACE_PERIOD) {
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+}
+
+/// Wait for a command or every member of a pipeline, stopping all of them if
+/// `timeout` elapses. The exit status is the one of the last process.
+///
+/// `estimate` is how long the command usually takes, used to report the time
+/// remaining.
+fn wait_all(
+    name: &str,
+    mut processes: Vec<Popen>,
+    timeout: Option<Duration>,
+    estimate: Option<Duration>,
+) -> Result<ExitStatus> {
+    let start = Instant::now();
+    let mut last_report = start;
+    loop {
+        let last = processes
+            .last_mut()
+            .expect("at least one process was started");
+        if let Some(status) = last.wait_timeout(POLL_INTERVAL)? {
+            // the producers of a pipeline (`yes`) only exit once they notice
+            // the consumer is gone
+            for process in processes.iter_mut() {
+                if let Ok(None) = process.wait_timeout(POLL_INTERVAL) {
+                    stop(process);
+                }
+            }
+            return Ok(status);
+        }
+        if interrupted() {
+            for process in processes.iter_mut().rev() {
+                stop(process);
+            }
+            return Err(UpdateError::Interrupted {
+                command: name.to_owned(),
+            });
+        }
+        let elapsed = start.elapsed();
+        if timeout.is_some_and(|timeout| elapsed >= timeout) {
+            for process in processes.iter_mut().rev() {
+                stop(process);
+            }
+            return Err(UpdateError::TimedOut {
+                command: name.to_owned(),
+                seconds: elapsed.as_secs(),
+            });
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            match estimate.map(|estimate| estimate.checked_sub(elapsed)) {
+                Some(Some(remaining)) => log::info!(
+                    "`{name}` is still running ({}s elapsed, about {}m remaining)",
+                    elapsed.as_secs(),
+                    remaining.as_secs().div_ceil(60)
+                ),
+                Some(None) => log::info!(
+                    "`{name}` is still running ({}s elapsed, longer than usual)",
+                    elapsed.as_secs()
+                ),
+                None => log::info!("`{name}` is still running ({}s elapsed)", elapsed.as_secs()),
+            }
+            last_report = Instant::now();
+        }
+    }
+}
+
+pub fn run(name: &str, exec: Exec, timeout: Option<Duration>) -> Result<ExitStatus> {
+    wait_all(name, vec![exec.popen()?], timeout, None)
+}
+
+pub fn run_pipeline(
+    name: &str,
+    pipeline: Pipeline,
+    timeout: Option<Duration>,
+    estimate: Option<Duration>,
+) -> Result<ExitStatus> {
+    wait_all(name, pipeline.popen()?, timeout, estimate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(60 * 60)));
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for duration in [
+            "",
+            "m",
+            "-1s",
+            "1.5h",
+            "1h30m",
+            "10d",
+            " 10s",
+            "99999999999999999999h",
+        ] {
+            assert!(parse_duration(duration).is_err(), "{duration}");
+        }
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+    }
+
+    #[test]
+    fn stage_timeouts() {
+        assert_eq!(
+            parse_stage_timeout("build=30m"),
+            Ok((Stage::Build, Duration::from_secs(30 * 60)))
+        );
+        assert_eq!(
+            parse_stage_timeout("remove-old=10"),
+            Ok((Stage::RemoveOld, Duration::from_secs(10)))
+        );
+        for timeout in ["build", "build=", "=30m", "compile=30m", "build=30x"] {
+            assert!(parse_stage_timeout(timeout).is_err(), "{timeout}");
+        }
+    }
+}
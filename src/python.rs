@@ -0,0 +1,50 @@
+use std::collections::BTreeSet;
+
+use regex::Regex;
+use subprocess::{Exec, Redirection};
+
+use crate::error::Result;
+
+/// `3.12` from `3.12.7-1`.
+fn major_minor(version: &str) -> String {
+    version.split('.').take(2).collect::<Vec<_>>().join(".")
+}
+
+/// Major and minor version of the system interpreter.
+pub fn interpreter_version() -> Result<Option<String>> {
+    let capture = Exec::cmd("python")
+        .args(&["-c", "import sys; print('%d.%d' % sys.version_info[:2])"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    Ok(capture
+        .success()
+        .then(|| capture.stdout_str().trim().to_owned()))
+}
+
+/// The Python version a package was built for, from its site-packages path.
+pub fn package_version(files: &BTreeSet<String>) -> Option<String> {
+    let site_packages = Regex::new(r"^/usr/lib/python(\d+\.\d+)/site-packages/").unwrap();
+    files
+        .iter()
+        .find_map(|path| Some(site_packages.captures(path)?[1].to_owned()))
+}
+
+/// An upgrade of python to a new minor version waiting in the sync
+/// databases, as (installed, available).
+pub fn pending_upgrade() -> Result<Option<(String, String)>> {
+    let capture = Exec::cmd("pacman")
+        .args(&["-Qu", "python"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    // `python 3.12.7-1 -> 3.13.1-1`
+    let stdout = capture.stdout_str();
+    let mut words = stdout.split_whitespace().skip(1);
+    let (Some(installed), Some("->"), Some(available)) = (words.next(), words.next(), words.next())
+    else {
+        return Ok(None);
+    };
+    let (installed, available) = (major_minor(installed), major_minor(available));
+    Ok((installed != available).then_some((installed, available)))
+}
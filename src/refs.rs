@@ -0,0 +1,59 @@
+use crate::error::Result;
+use crate::git;
+use crate::term;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RefsArgs {
+    #[arg(
+        short,
+        long,
+        num_args = 1,
+        default_value = "qtile",
+        conflicts_with = "path"
+    )]
+    fork: Option<String>,
+    #[arg(short, long, num_args = 1, default_value = None)]
+    path: Option<String>,
+    /// Only list branches
+    #[arg(long, default_value_t = false, conflicts_with = "tags")]
+    branches: bool,
+    /// Only list tags
+    #[arg(long, default_value_t = false)]
+    tags: bool,
+    /// Show the commit date of each ref (fetches the refs)
+    #[arg(long, default_value_t = false)]
+    dates: bool,
+}
+
+pub fn refs(args: &RefsArgs) -> Result<()> {
+    let url = git::remote_url(args.fork.as_deref(), args.path.as_deref());
+    log::info!("listing refs of `{url}`");
+    let mut refs = git::ls_remote(&url)?;
+    refs.retain(|r| (r.branch().is_some() && !args.tags) || (r.tag().is_some() && !args.branches));
+    let times = if args.dates {
+        let times = git::commit_times(&url, &refs)?;
+        refs.sort_by_key(|r| std::cmp::Reverse(times.get(&r.oid).copied()));
+        times
+    } else {
+        Default::default()
+    };
+    for r in &refs {
+        let (kind, name) = match r.branch() {
+            Some(branch) => ("branch", branch),
+            None => ("tag", r.tag().unwrap_or(&r.name)),
+        };
+        let short = &r.oid.to_string()[..10];
+        match times.get(&r.oid) {
+            Some(time) => println!(
+                "{}",
+                term::fit(format!(
+                    "{:<6} {short} {} {name}",
+                    kind,
+                    git::format_date(*time)
+                ))
+            ),
+            None => println!("{}", term::fit(format!("{:<6} {short} {name}", kind))),
+        }
+    }
+    Ok(())
+}
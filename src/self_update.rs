@@ -0,0 +1,317 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use crate::cache;
+use crate::error::{Result, UpdateError};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/ervinpopescu/update-qtile/releases/latest";
+const AUR_INFO_URL: &str = "https://aur.archlinux.org/rpc/v5/info?arg[]=";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelfUpdateArgs {
+    /// Only report whether a newer version exists
+    #[arg(long, default_value_t = false)]
+    check: bool,
+}
+
+/// A version ordered by its numbers, then releases after their pre-releases.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    numbers: Vec<u64>,
+    release: bool,
+    pre_release: Option<Vec<PreReleasePart>>,
+}
+
+/// A run of digits or of other characters in a pre-release, so that `rc10`
+/// comes after `rc2`. Numbers come before text, as in semver.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum PreReleasePart {
+    Number(u64),
+    Text(String),
+}
+
+fn parse_pre_release(pre_release: &str) -> Vec<PreReleasePart> {
+    let mut parts = vec![];
+    for identifier in pre_release.split(['.', '-']) {
+        let mut rest = identifier;
+        while let Some(first) = rest.chars().next() {
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let (run, tail) = rest.split_at(end);
+            parts.push(match run.parse() {
+                Ok(number) if first.is_ascii_digit() => PreReleasePart::Number(number),
+                _ => PreReleasePart::Text(run.to_owned()),
+            });
+            rest = tail;
+        }
+    }
+    parts
+}
+
+/// Parse `v1.2.3`, `1.2.3-rc1`, `0.23.0rc1` or pacman's `0.29.0.r12.gabc1234-1`.
+///
+/// Only a first letter right after `-` or a digit starts a pre-release, the
+/// `r12.gabc1234` of -git versions comes after a dot.
+pub fn parse_version(version: &str) -> Version {
+    let version = version.trim_start_matches('v');
+    let version = version.split('+').next().unwrap_or(version);
+    let pre_release_start = version
+        .char_indices()
+        .find(|(_, c)| c.is_ascii_alphabetic())
+        .filter(|(index, _)| {
+            version[..*index]
+                .chars()
+                .next_back()
+                .is_some_and(|before| before == '-' || before.is_ascii_digit())
+        })
+        .map(|(index, _)| index);
+    let (numbers, pre_release) = match pre_release_start {
+        Some(index) => (
+            &version[..index],
+            Some(parse_pre_release(&version[index..])),
+        ),
+        None => (version, None),
+    };
+    Version {
+        numbers: numbers
+            .split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect(),
+        release: pre_release.is_none(),
+        pre_release,
+    }
+}
+
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    parse_version(candidate) > parse_version(current)
+}
+
+fn fetch(url: &str) -> Result<String> {
+    let capture = Exec::cmd("curl")
+        .args(&["-fsSL", "-H", "Accept: application/json", url])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()?;
+    if !capture.success() {
+        return Err(UpdateError::FetchFailed {
+            url: url.to_owned(),
+            message: capture.stdout_str().trim().to_owned(),
+        });
+    }
+    Ok(capture.stdout_str())
+}
+
+fn fetch_json(url: &str) -> Result<serde_json::Value> {
+    Ok(serde_json::from_str(&fetch(url)?)?)
+}
+
+/// The checksum of `name` in a `sha256sum` output, or the only one in a
+/// `<name>.sha256` file that has no file names.
+fn checksum_for(sums: &str, name: &str) -> Option<String> {
+    let lines = sums
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    lines.iter().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let hash = fields.next()?;
+        match fields.next() {
+            // `*` marks files hashed in binary mode
+            Some(file) if file.trim_start_matches('*') == name => Some(hash.to_lowercase()),
+            None if lines.len() == 1 => Some(hash.to_lowercase()),
+            _ => None,
+        }
+    })
+}
+
+/// The published sha256 of the release asset `name`: the digest GitHub
+/// records for it, or the one in a `<name>.sha256` or `SHA256SUMS` asset.
+fn published_sha256(assets: &[serde_json::Value], name: &str) -> Result<Option<String>> {
+    let asset = |wanted: &str| assets.iter().find(|asset| asset["name"] == wanted);
+    if let Some(digest) = asset(name)
+        .and_then(|asset| asset["digest"].as_str())
+        .and_then(|digest| digest.strip_prefix("sha256:"))
+    {
+        return Ok(Some(digest.to_lowercase()));
+    }
+    for sums in [format!("{name}.sha256"), "SHA256SUMS".to_owned()] {
+        if let Some(url) = asset(&sums).and_then(|asset| asset["browser_download_url"].as_str()) {
+            return Ok(checksum_for(&fetch(url)?, name));
+        }
+    }
+    Ok(None)
+}
+
+/// The package owning `exe`, if it was installed through pacman.
+fn owning_package(exe: &Path) -> Result<Option<String>> {
+    let capture = Exec::cmd("pacman")
+        .arg("-Qqo")
+        .arg(exe)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    Ok(capture
+        .success()
+        .then(|| capture.stdout_str().trim().to_owned()))
+}
+
+fn check_aur(package: &str) -> Result<()> {
+    let installed = Exec::cmd("pacman")
+        .args(&["-Q", package])
+        .stdout(Redirection::Pipe)
+        .capture()?
+        .stdout_str();
+    let installed = installed.split_whitespace().nth(1).unwrap_or_default();
+    let info = fetch_json(&format!("{AUR_INFO_URL}{package}"))?;
+    let Some(latest) = info["results"][0]["Version"].as_str() else {
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "`{package}` was not found on the AUR"
+        )));
+    };
+    if is_newer(latest, installed) {
+        log::info!("{package} {latest} is available (installed: {installed})");
+        log::info!("update it with your AUR helper, e.g. `yay -S {package}`");
+    } else {
+        log::info!("{package} is up to date ({installed})");
+    }
+    Ok(())
+}
+
+pub fn self_update(args: &SelfUpdateArgs) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    if let Some(package) = owning_package(&exe)? {
+        log::info!("{:?} is managed by the `{package}` package", exe);
+        return check_aur(&package);
+    }
+
+    let current = env!("CARGO_PKG_VERSION");
+    let release = fetch_json(RELEASES_URL)?;
+    let Some(latest) = release["tag_name"].as_str() else {
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "no release found at {RELEASES_URL}"
+        )));
+    };
+    if !is_newer(latest, current) {
+        log::info!("update-qtile is up to date ({current})");
+        return Ok(());
+    }
+    log::info!("update-qtile {latest} is available (installed: {current})");
+    if args.check {
+        return Ok(());
+    }
+
+    let assets = release["assets"].as_array().cloned().unwrap_or_default();
+    let asset = assets
+        .iter()
+        .filter_map(|asset| {
+            Some((
+                asset["name"].as_str()?,
+                asset["browser_download_url"].as_str()?,
+            ))
+        })
+        .filter(|(name, _)| name.starts_with("update-qtile"))
+        .find(|(name, _)| *name == "update-qtile" || name.contains(std::env::consts::ARCH));
+    let Some((name, url)) = asset else {
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "release {latest} has no binary for {}",
+            std::env::consts::ARCH
+        )));
+    };
+
+    let Some(expected) = published_sha256(&assets, name)? else {
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "release {latest} publishes no checksum for {name}, refusing to install it"
+        )));
+    };
+
+    log::info!("downloading {name}");
+    let download = exe.with_extension("new");
+    let exit_status = Exec::cmd("curl")
+        .args(&["-fsSL", "-o"])
+        .arg(&download)
+        .arg(url)
+        .join()?;
+    if !exit_status.success() {
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "could not download {url}"
+        )));
+    }
+    let actual = cache::sha256(&download)?;
+    if actual != expected {
+        std::fs::remove_file(&download)?;
+        return Err(UpdateError::SelfUpdateFailed(format!(
+            "{name} has sha256 {actual} but the release publishes {expected}, it was not installed"
+        )));
+    }
+    std::fs::set_permissions(&download, std::fs::Permissions::from_mode(0o755))?;
+    std::fs::rename(&download, &exe)?;
+    log::info!("updated {:?} to {latest}", exe);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_versions() {
+        assert!(is_newer("v1.2.0", "1.1.9"));
+        assert!(is_newer("0.10.0", "0.9.1"));
+        assert!(is_newer("1.2.1", "1.2"));
+        assert!(!is_newer("1.1.9", "1.2.0"));
+    }
+
+    #[test]
+    fn equal_versions() {
+        assert!(!is_newer("1.2.0", "1.2.0"));
+        assert!(!is_newer("v1.2.0", "1.2.0"));
+        assert!(!is_newer("1.2.0+build5", "1.2.0"));
+    }
+
+    #[test]
+    fn pre_releases() {
+        assert!(is_newer("1.2.0", "1.2.0-rc1"));
+        assert!(!is_newer("1.2.0-rc1", "1.2.0"));
+        assert!(is_newer("1.2.0-rc2", "1.2.0-rc1"));
+        assert!(is_newer("1.2.0-rc10", "1.2.0-rc2"));
+        assert!(!is_newer("1.2.0-rc2", "1.2.0-rc10"));
+        assert!(is_newer("1.2.0-beta.11", "1.2.0-beta.2"));
+        assert!(is_newer("1.2.0-beta", "1.2.0-alpha.5"));
+        assert!(is_newer("0.23.0rc10", "0.23.0rc9"));
+        assert!(is_newer("1.2.0-rc1", "1.1.9"));
+        assert!(is_newer("v0.23.0", "0.23.0rc1"));
+        assert!(is_newer("v0.23.0rc1", "0.22.1"));
+    }
+
+    #[test]
+    fn git_package_versions() {
+        // commits after a release are not a pre-release of it
+        assert!(!is_newer("v0.29.0", "0.29.0.r12.gabc1234-1"));
+        assert!(!is_newer("v0.29.0", "0.29.0.r12.g1a2b3c4-1"));
+        assert!(is_newer("v0.30.0", "0.29.0.r12.g1a2b3c4-1"));
+    }
+
+    #[test]
+    fn checksums() {
+        let sums = "\
+0123abcd  update-qtile-x86_64
+4567EF89 *update-qtile-aarch64
+";
+        assert_eq!(
+            checksum_for(sums, "update-qtile-x86_64").as_deref(),
+            Some("0123abcd")
+        );
+        assert_eq!(
+            checksum_for(sums, "update-qtile-aarch64").as_deref(),
+            Some("4567ef89")
+        );
+        assert_eq!(checksum_for(sums, "update-qtile"), None);
+        assert_eq!(
+            checksum_for("0123abcd\n", "update-qtile").as_deref(),
+            Some("0123abcd")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::ipc;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedWindow {
+    pub id: u64,
+    pub wm_class: Vec<String>,
+    pub name: String,
+    pub group: String,
+}
+
+/// Which window is on which group and which group is on which screen.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Layout {
+    pub windows: Vec<SavedWindow>,
+    pub screens: BTreeMap<String, u64>,
+}
+
+fn windows() -> Result<Vec<SavedWindow>> {
+    let windows = ipc::call(&[], "windows", &[])?;
+    Ok(windows
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|window| {
+            Some(SavedWindow {
+                id: window["id"].as_u64()?,
+                wm_class: serde_json::from_value(window["wm_class"].clone()).unwrap_or_default(),
+                name: window["name"].as_str().unwrap_or_default().to_owned(),
+                group: window["group"].as_str()?.to_owned(),
+            })
+        })
+        .collect())
+}
+
+impl Layout {
+    pub fn capture() -> Result<Self> {
+        let groups = ipc::call(&[], "groups", &[])?;
+        let screens = groups
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, group)| Some((name.clone(), group["screen"].as_u64()?)))
+            .collect();
+        Ok(Self {
+            windows: windows()?,
+            screens,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Move windows back to their groups and groups back to their screens.
+    ///
+    /// Windows are matched by id first (X11 keeps them across restarts), then
+    /// by class and title.
+    pub fn restore(&self) -> Result<()> {
+        let current = windows()?;
+        let mut moved = 0;
+        for saved in &self.windows {
+            let window = current
+                .iter()
+                .find(|window| window.id == saved.id)
+                .or_else(|| {
+                    current.iter().find(|window| {
+                        window.wm_class == saved.wm_class && window.name == saved.name
+                    })
+                });
+            let Some(window) = window.filter(|window| window.group != saved.group) else {
+                continue;
+            };
+            let id = window.id.to_string();
+            match ipc::call(&["window", &id], "togroup", &[&saved.group]) {
+                Ok(_) => moved += 1,
+                Err(err) => log::warn!("could not move `{}`: {err}", saved.name),
+            }
+        }
+        for (group, screen) in &self.screens {
+            let screen = screen.to_string();
+            if let Err(err) = ipc::call(&["group", group], "toscreen", &[&screen]) {
+                log::warn!("could not show group `{group}` on screen {screen}: {err}");
+            }
+        }
+        log::info!("restored layout, moved {moved} window(s)");
+        Ok(())
+    }
+}
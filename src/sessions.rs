@@ -0,0 +1,78 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::files;
+
+/// The display server qtile is used on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    X11,
+    Wayland,
+}
+
+impl Backend {
+    /// Where display managers look for the session files of this backend.
+    fn session_dir(self) -> &'static str {
+        match self {
+            Backend::X11 => "/usr/share/xsessions/",
+            Backend::Wayland => "/usr/share/wayland-sessions/",
+        }
+    }
+}
+
+/// The session files among `package_files`, only those of `backend` if given.
+pub fn session_files(package_files: &BTreeSet<String>, backend: Option<Backend>) -> Vec<String> {
+    let sessions = package_files
+        .iter()
+        .filter(|path| path.ends_with(".desktop"))
+        .filter(|path| match backend {
+            Some(backend) => path.starts_with(backend.session_dir()),
+            None => [Backend::X11, Backend::Wayland]
+                .iter()
+                .any(|backend| path.starts_with(backend.session_dir())),
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if let Some(backend) = backend.filter(|_| sessions.is_empty()) {
+        log::warn!(
+            "the new package has no session file in {}, display managers won't offer qtile there",
+            backend.session_dir()
+        );
+    }
+    sessions
+}
+
+/// The files among `sessions` that were changed after qtile-git installed
+/// them, or that exist without belonging to any package.
+pub fn customized(sessions: &[String]) -> Result<Vec<String>> {
+    let modified = files::check_installed("qtile-git")?
+        .into_iter()
+        .filter(|(_, problem)| files::is_modification(problem))
+        .map(|(path, _)| path)
+        .collect::<BTreeSet<_>>();
+    let unowned = files::unowned(&sessions.iter().cloned().collect())?;
+    Ok(sessions
+        .iter()
+        .filter(|path| modified.contains(*path) || unowned.contains(path))
+        .cloned()
+        .collect())
+}
+
+/// Copy `sessions` to `dir`, returns (original, backup) pairs.
+pub fn backup(sessions: &[String], dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    std::fs::create_dir_all(dir)?;
+    sessions
+        .iter()
+        .map(|path| {
+            let backup = dir.join(path.trim_start_matches('/').replace('/', "%"));
+            std::fs::copy(path, &backup)?;
+            log::info!("backed up customized {path} to {:?}", backup);
+            Ok((path.clone(), backup))
+        })
+        .collect()
+}
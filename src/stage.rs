@@ -0,0 +1,216 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, UpdateError};
+
+/// The steps an update goes through, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Clean,
+    Clone,
+    Patch,
+    Build,
+    RemoveOld,
+    Install,
+    Restart,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 7] = [
+        Stage::Clean,
+        Stage::Clone,
+        Stage::Patch,
+        Stage::Build,
+        Stage::RemoveOld,
+        Stage::Install,
+        Stage::Restart,
+    ];
+
+    pub fn next(self) -> Option<Stage> {
+        Self::ALL.into_iter().find(|stage| *stage > self)
+    }
+
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .expect("no stage is skipped")
+            .get_name()
+            .to_owned()
+    }
+}
+
+/// Progress of the current run, persisted after every stage so that a failed
+/// run can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    pub source: String,
+    pub completed: Option<Stage>,
+    /// Checksum of the built package, verified before installing it.
+    pub package_sha256: Option<String>,
+}
+
+impl RunState {
+    pub fn new(source: String) -> Self {
+        Self {
+            source,
+            completed: None,
+            package_sha256: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn clear(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The first stage that still has to run.
+    pub fn next_stage(&self) -> Option<Stage> {
+        match self.completed {
+            Some(stage) => stage.next(),
+            None => Some(Stage::Clean),
+        }
+    }
+}
+
+pub fn state_path(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("state.json")
+}
+
+pub fn lock_path(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("lock")
+}
+
+/// Guards against two updates running at once, released on drop.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut f) => {
+                    write!(f, "{}", std::process::id())?;
+                    return Ok(Self {
+                        path: path.to_owned(),
+                    });
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+            // an unreadable pid is left by a run that crashed while taking
+            // the lock
+            let pid = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<u32>().ok());
+            if let Some(pid) = pid.filter(|pid| Path::new("/proc").join(pid.to_string()).exists()) {
+                return Err(UpdateError::AlreadyRunning {
+                    pid: pid.to_string(),
+                });
+            }
+            log::warn!("removing stale lock file {:?}", path);
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_stage() {
+        let mut state = RunState::new("https://github.com/qtile/qtile.git".to_owned());
+        assert_eq!(state.next_stage(), Some(Stage::Clean));
+        state.completed = Some(Stage::Build);
+        assert_eq!(state.next_stage(), Some(Stage::RemoveOld));
+        state.completed = Some(Stage::Restart);
+        assert_eq!(state.next_stage(), None);
+    }
+
+    #[test]
+    fn resume_from_saved_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("update-qtile").join("state.json");
+        assert!(RunState::load(&path).unwrap().is_none());
+
+        let mut state = RunState::new("https://github.com/qtile/qtile.git#commit=abc".to_owned());
+        state.completed = Some(Stage::Build);
+        state.package_sha256 = Some("0123abcd".to_owned());
+        state.save(&path).unwrap();
+        let loaded = RunState::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.source, state.source);
+        assert_eq!(loaded.next_stage(), Some(Stage::RemoveOld));
+        assert_eq!(loaded.package_sha256.as_deref(), Some("0123abcd"));
+
+        RunState::clear(&path).unwrap();
+        assert!(RunState::load(&path).unwrap().is_none());
+        // clearing twice is fine
+        RunState::clear(&path).unwrap();
+    }
+
+    #[test]
+    fn lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let lock = RunLock::acquire(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+        assert!(matches!(
+            RunLock::acquire(&path),
+            Err(UpdateError::AlreadyRunning { .. })
+        ));
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        // the last one is above the largest pid Linux hands out
+        for contents in ["", "garbage", "../self", "4294967295"] {
+            std::fs::write(&path, contents).unwrap();
+            let lock = RunLock::acquire(&path).unwrap();
+            drop(lock);
+        }
+    }
+}
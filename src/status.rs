@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use subprocess::{Exec, Redirection};
+
+use crate::error::Result;
+use crate::ipc;
+use crate::stage::{self, RunState};
+
+pub fn pending_login_path(cache_home: &Path) -> PathBuf {
+    cache_home.join("update-qtile").join("pending-login")
+}
+
+/// Version of the installed qtile-git package, e.g. `0.29.0.r12.gabc1234-1`.
+pub fn installed_version() -> Result<Option<String>> {
+    let capture = Exec::cmd("pacman")
+        .args(&["-Q", "qtile-git"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    Ok(capture
+        .success()
+        .then(|| {
+            capture
+                .stdout_str()
+                .split_whitespace()
+                .nth(1)
+                .map(str::to_owned)
+        })
+        .flatten())
+}
+
+/// Version reported by the running qtile.
+pub fn running_version() -> Option<String> {
+    let info = ipc::call(&[], "qtile_info", &[]).ok()?;
+    info["version"].as_str().map(str::to_owned)
+}
+
+/// Whether a package version and a qtile version describe the same build.
+///
+/// pacman and qtile format -git versions differently, but both carry the
+/// abbreviated commit after a `g`. Releases are compared without the epoch
+/// and pkgrel of the package.
+fn same_build(package: &str, running: &str) -> bool {
+    let commit = Regex::new(r"g([0-9a-f]{7,})").unwrap();
+    match (commit.captures(package), commit.captures(running)) {
+        (Some(a), Some(b)) => a[1].starts_with(&b[1]) || b[1].starts_with(&a[1]),
+        _ => {
+            let version = package
+                .split_once(':')
+                .map_or(package, |(_, version)| version);
+            let version = version
+                .rsplit_once('-')
+                .map_or(version, |(version, _)| version);
+            version == running.trim_start_matches('v')
+        }
+    }
+}
+
+pub fn status(cache_home: &Path) -> Result<()> {
+    let installed = installed_version()?;
+    let running = running_version();
+    match &installed {
+        Some(version) => println!("installed: qtile-git {version}"),
+        None => println!("installed: qtile-git is not installed"),
+    }
+    match &running {
+        Some(version) => println!("running:   qtile {version}"),
+        None => println!("running:   qtile is not running or not reachable"),
+    }
+
+    let marker = pending_login_path(cache_home);
+    if marker.exists() {
+        let up_to_date = installed
+            .as_deref()
+            .zip(running.as_deref())
+            .is_some_and(|(installed, running)| same_build(installed, running));
+        if up_to_date {
+            std::fs::remove_file(&marker)?;
+        } else {
+            println!("new version pending login");
+        }
+    }
+
+    if let Some(state) = RunState::load(&stage::state_path(cache_home))? {
+        if let Some(stage) = state.next_stage() {
+            println!(
+                "unfinished run for `{}`, resume it from stage `{}` with `update-qtile update --resume`",
+                state.source,
+                stage.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_commit() {
+        assert!(same_build(
+            "0.29.0.r12.gabc1234-1",
+            "0.29.1.dev12+gabc1234d"
+        ));
+        assert!(same_build(
+            "0.29.0.r12.gabc1234def-1",
+            "0.29.1.dev12+gabc1234"
+        ));
+    }
+
+    #[test]
+    fn different_commit() {
+        assert!(!same_build(
+            "0.29.0.r12.gabc1234-1",
+            "0.29.1.dev13+gdef5678"
+        ));
+    }
+
+    #[test]
+    fn releases() {
+        assert!(same_build("0.29.0-1", "0.29.0"));
+        assert!(same_build("1:0.29.0-2", "v0.29.0"));
+        assert!(!same_build("0.29.0-1", "0.29"));
+        assert!(!same_build("0.29.0-1", "0.29.1"));
+    }
+}
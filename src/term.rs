@@ -0,0 +1,71 @@
+use std::io::IsTerminal;
+
+use text_io::read;
+
+use crate::error::{Result, UpdateError};
+
+/// Whether someone can answer prompts, which isn't the case under systemd,
+/// cron or in a pipe.
+pub fn interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Whether the log is colored, only on a terminal and unless `NO_COLOR` is set.
+pub fn colors() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Ask a question that defaults to yes. Without a terminal nobody can answer
+/// it, so the answer is no.
+pub fn confirm(question: &str) -> bool {
+    if !interactive() {
+        log::warn!("{question} (no terminal to answer on, assuming no)");
+        return false;
+    }
+    log::info!("{question} [Y/n]");
+    let ans: String = read!("{}\n");
+    ["Y", "y", ""].contains(&ans.as_str())
+}
+
+/// Ask `question` and return the answer. `what` is what needs the answer,
+/// for the error when there is no terminal to answer on.
+pub fn ask(question: &str, what: &str) -> Result<String> {
+    if !interactive() {
+        return Err(UpdateError::NoTerminal(what.to_owned()));
+    }
+    log::info!("{question}");
+    let mut ans = String::new();
+    if std::io::stdin().read_line(&mut ans)? == 0 {
+        // stdin was closed
+        return Err(UpdateError::NoTerminal(what.to_owned()));
+    }
+    Ok(ans.trim().to_owned())
+}
+
+/// Columns of the terminal, `None` when stdout isn't one.
+pub fn width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a winsize through the pointer
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(size.ws_col.into())
+}
+
+/// `line` cut to the width of the terminal, untouched when not on one.
+pub fn fit(line: String) -> String {
+    match width() {
+        Some(width) if line.chars().count() > width => {
+            let mut cut: String = line.chars().take(width.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+        _ => line,
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use crate::error::{Result, UpdateError};
+use crate::{files, python, sessions};
+
+/// Problems with the files that mean the package no longer is what was
+/// installed, pacman reports them on deleted files too.
+fn is_damage(problem: &str) -> bool {
+    files::is_modification(problem) || problem.contains("No such file")
+}
+
+fn report(ok: bool, check: &str, failed: &mut usize) {
+    if ok {
+        println!("ok    {check}");
+    } else {
+        println!("FAIL  {check}");
+        *failed += 1;
+    }
+}
+
+/// Whether the installed libqtile can be imported, with python's error if not.
+fn import_libqtile() -> Result<std::result::Result<(), String>> {
+    let capture = Exec::cmd("python")
+        .args(&["-c", "import libqtile.core.manager"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()?;
+    if capture.success() {
+        return Ok(Ok(()));
+    }
+    let output = capture.stdout_str();
+    Ok(Err(output
+        .trim()
+        .lines()
+        .last()
+        .unwrap_or_default()
+        .to_owned()))
+}
+
+/// Check the installed qtile-git against its package: modified or deleted
+/// files, the binary, the session files and that python can import it.
+pub fn verify() -> Result<()> {
+    let Some(installed) = files::installed_files("qtile-git")? else {
+        return Err(UpdateError::Unhealthy("it is not installed".to_owned()));
+    };
+    let mut failed = 0;
+
+    let problems = files::check_installed("qtile-git")?;
+    let damaged = problems
+        .iter()
+        .filter(|(_, problem)| is_damage(problem))
+        .collect::<Vec<_>>();
+    report(
+        damaged.is_empty(),
+        &format!("{} files match the package", installed.len()),
+        &mut failed,
+    );
+    for (path, problem) in &damaged {
+        println!("        {path}: {problem}");
+    }
+    let other = problems.len() - damaged.len();
+    if other > 0 {
+        log::info!("{other} file(s) differ only in metadata, see `pacman -Qkk qtile-git`");
+    }
+
+    report(
+        Path::new("/usr/bin/qtile").is_file(),
+        "/usr/bin/qtile exists",
+        &mut failed,
+    );
+    let sessions = sessions::session_files(&installed, None);
+    report(
+        !sessions.is_empty(),
+        "the package has session files",
+        &mut failed,
+    );
+    for session in &sessions {
+        report(
+            Path::new(session).is_file(),
+            &format!("{session} exists"),
+            &mut failed,
+        );
+    }
+
+    if let (Some(package), Some(interpreter)) = (
+        python::package_version(&installed),
+        python::interpreter_version()?,
+    ) {
+        report(
+            package == interpreter,
+            &format!("built for python {package}, python is {interpreter}"),
+            &mut failed,
+        );
+    }
+    match import_libqtile()? {
+        Ok(()) => report(true, "python can import libqtile", &mut failed),
+        Err(error) => {
+            report(false, "python can import libqtile", &mut failed);
+            println!("        {error}");
+        }
+    }
+
+    if failed > 0 {
+        return Err(UpdateError::Unhealthy(format!(
+            "{failed} check(s) failed, reinstall qtile-git or rebuild it with `update-qtile update`"
+        )));
+    }
+    log::info!("qtile-git is healthy");
+    Ok(())
+}
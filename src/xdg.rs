@@ -0,0 +1,26 @@
+use std::path::{Path, PathBuf};
+
+pub fn home() -> String {
+    std::env::var("HOME").unwrap_or_default()
+}
+
+/// The directory in `var`, or `fallback` in the home directory when it is
+/// unset or empty.
+fn base_dir(var: &str, fallback: &str) -> PathBuf {
+    match std::env::var(var) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&home()).join(fallback),
+    }
+}
+
+pub fn cache_home() -> PathBuf {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+pub fn config_home() -> PathBuf {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn data_home() -> PathBuf {
+    base_dir("XDG_DATA_HOME", ".local/share")
+}